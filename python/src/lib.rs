@@ -0,0 +1,137 @@
+// Python bindings for the rules engine. The module is built on the engine of
+// the library crate, so Python code plays by exactly the same rules as the
+// server.
+//
+//     import hnefatafl
+//     game = hnefatafl.Game("brandubh")
+//     game.play(game.legal_moves()[0])
+
+use pyo3::exceptions::PyValueError;
+use pyo3::prelude::*;
+
+use tafl::engine::{self, parse_square, position_string, square_name, Move, Piece, Rules, Variant};
+
+fn value_error(message: String) -> PyErr {
+    PyValueError::new_err(message)
+}
+
+fn parse_variant(name: &str) -> PyResult<Variant> {
+    Variant::from_name(name).ok_or_else(|| value_error(format!("Unknown variant: {}", name)))
+}
+
+/// A game of any variant. Moves and squares use the server's notation,
+/// e.g. "d1-d3" and "d4"; sides are "attacker" and "defender".
+#[pyclass(module = "hnefatafl")]
+#[derive(Clone)]
+struct Game {
+    game: engine::Game,
+}
+
+#[pymethods]
+impl Game {
+    /// Starts a game of the variant from its initial position.
+    #[new]
+    fn new(variant: &str) -> PyResult<Self> {
+        Ok(Game { game: engine::Game::new(parse_variant(variant)?) })
+    }
+
+    /// Starts a game from a position string, e.g. "3a3/3a3/3d3/aadkdaa/3d3/3a3/3a3 a".
+    #[staticmethod]
+    fn from_position(variant: &str, position: &str) -> PyResult<Self> {
+        let game = engine::Game::from_position(parse_variant(variant)?, position).map_err(value_error)?;
+        Ok(Game { game })
+    }
+
+    #[getter]
+    fn variant(&self) -> &'static str {
+        self.game.variant().name()
+    }
+
+    #[getter]
+    fn size(&self) -> usize {
+        self.game.size()
+    }
+
+    #[getter]
+    fn side_to_move(&self) -> &'static str {
+        self.game.side_to_move().name()
+    }
+
+    /// The winning side, or None while the game goes on
+    #[getter]
+    fn winner(&self) -> Option<&'static str> {
+        self.game.winner().map(|side| side.name())
+    }
+
+    #[getter]
+    fn is_over(&self) -> bool {
+        self.game.winner().is_some()
+    }
+
+    /// Zobrist hash of the position, the same as the server's
+    #[getter]
+    fn hash(&self) -> u64 {
+        self.game.position_hash()
+    }
+
+    /// "attacker", "defender", "king" or None
+    fn piece_at(&self, square: &str) -> PyResult<Option<&'static str>> {
+        let pos = parse_square(square, self.game.size()).map_err(value_error)?;
+        Ok(self.game.piece_at(pos).map(|piece| match piece {
+            Piece::Attacker => "attacker",
+            Piece::Defender => "defender",
+            Piece::King => "king",
+        }))
+    }
+
+    fn legal_moves(&self) -> Vec<String> {
+        let size = self.game.size();
+        self.game.legal_moves().iter().map(|mv| mv.notation(size)).collect()
+    }
+
+    /// Plays a legal move and returns the squares of the captured pieces.
+    fn play(&mut self, mv: &str) -> PyResult<Vec<String>> {
+        let size = self.game.size();
+        let mv = Move::parse(mv, size).map_err(value_error)?;
+        let captured = self.game.play(mv).map_err(value_error)?;
+        Ok(captured.into_iter().map(|pos| square_name(pos, size)).collect())
+    }
+
+    /// Takes back the last move.
+    fn undo(&mut self) -> PyResult<()> {
+        self.game.undo().map_err(value_error)
+    }
+
+    /// Moves played so far
+    fn history(&self) -> Vec<String> {
+        let size = self.game.size();
+        self.game.history().iter().map(|mv| mv.notation(size)).collect()
+    }
+
+    /// Position string in the format accepted by `from_position`
+    fn position(&self) -> String {
+        position_string(&self.game)
+    }
+
+    fn copy(&self) -> Self {
+        self.clone()
+    }
+
+    fn __repr__(&self) -> String {
+        format!("Game({:?}, {:?})", self.game.variant().name(), position_string(&self.game))
+    }
+}
+
+/// Names of the variants
+#[pyfunction]
+fn variants() -> Vec<&'static str> {
+    Variant::ALL.iter().map(|variant| variant.name()).collect()
+}
+
+#[pymodule]
+#[pyo3(name = "hnefatafl")]
+fn python_module(m: &Bound<'_, PyModule>) -> PyResult<()> {
+    m.add_class::<Game>()?;
+    m.add_function(wrap_pyfunction!(variants, m)?)?;
+    Ok(())
+}
@@ -0,0 +1,343 @@
+// Post-game analysis: every position of a finished game is searched by the
+// engine, and each move is judged by how much it lost against the best move.
+
+use std::time::Duration;
+
+use crate::engine::{Game, Move, Piece, Rules, Side};
+use crate::eval::{Weights, WIN_SCORE};
+use crate::search::{SearchLimits, Searcher};
+use crate::tablebase::{tablebase, Outcome, Tablebase};
+
+/// Thinking time for each analysed position
+pub const ANALYSIS_TIME_MS: u64 = 300;
+
+/// Evaluation loss, in the evaluation's units, from which a move is flagged
+const INACCURACY_LOSS: i32 = 50;
+const MISTAKE_LOSS: i32 = 120;
+const BLUNDER_LOSS: i32 = 300;
+
+/// Scores are capped here when comparing moves, so that the distance to a
+/// forced win does not count as a loss of its own
+const SCORE_CAP: i32 = 1_500;
+
+/// Scale of the evaluation when turning it into a win probability
+const WIN_PROBABILITY_SCALE: f64 = 400.0;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Judgement {
+    Good,
+    Inaccuracy,
+    Mistake,
+    Blunder,
+}
+
+impl Judgement {
+    fn from_loss(loss: i32) -> Self {
+        if loss >= BLUNDER_LOSS {
+            Judgement::Blunder
+        } else if loss >= MISTAKE_LOSS {
+            Judgement::Mistake
+        } else if loss >= INACCURACY_LOSS {
+            Judgement::Inaccuracy
+        } else {
+            Judgement::Good
+        }
+    }
+
+    /// Annotation symbol used in the record
+    pub fn symbol(&self) -> &'static str {
+        match self {
+            Judgement::Good => "",
+            Judgement::Inaccuracy => "?!",
+            Judgement::Mistake => "?",
+            Judgement::Blunder => "??",
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct AnnotatedMove {
+    pub ply: usize,
+    pub side: Side,
+    pub notation: String,
+    pub best_move: Option<String>,  // Engine choice, when it differs from the move played
+    pub eval: i32,                  // Evaluation after the move, for the attackers
+    pub loss: i32,                  // Evaluation lost against the best move
+    pub judgement: Judgement,
+    pub accuracy: f64,              // 0 to 100, from the drop in winning chances
+    pub missed_king_escape: bool,   // The king could have escaped but did not
+    pub missed_capture: bool,       // The best move captured and the move played did not
+    pub tablebase: Option<Outcome>, // Exact result after the move, for the side then to move
+}
+
+#[derive(Debug, Clone)]
+pub struct Analysis {
+    pub moves: Vec<AnnotatedMove>,
+    pub winner: Option<Side>,
+    pub attacker_accuracy: Option<f64>,
+    pub defender_accuracy: Option<f64>,
+}
+
+impl Analysis {
+    /// The moves in notation with their annotation symbols, numbered in
+    /// pairs of plies.
+    pub fn annotated_record(&self) -> String {
+        let mut record = Vec::new();
+        for annotated in &self.moves {
+            let text = format!("{}{}", annotated.notation, annotated.judgement.symbol());
+            if annotated.ply % 2 == 1 || record.is_empty() {
+                record.push(format!("{}. {}", annotated.ply.div_ceil(2), text));
+            } else {
+                record.push(text);
+            }
+        }
+        record.join(" ")
+    }
+}
+
+/// Winning chances, between 0 and 1, of a score from the mover's point of view
+fn win_probability(score: i32) -> f64 {
+    1.0 / (1.0 + (-(score as f64) / WIN_PROBABILITY_SCALE).exp())
+}
+
+/// Accuracy of a move from the drop in winning chances, in percent
+fn move_accuracy(best: i32, played: i32) -> f64 {
+    let drop = ((win_probability(best) - win_probability(played)) * 100.0).max(0.0);
+    (103.17 * (-0.04354 * drop).exp() - 3.17).clamp(0.0, 100.0)
+}
+
+/// Checks whether the king of the side to move can win in one move.
+fn king_escape_available<G: Rules>(game: &mut G) -> bool {
+    if game.side_to_move() != Side::Defender {
+        return false;
+    }
+    for mv in game.legal_moves() {
+        if game.piece_at(mv.from) != Some(Piece::King) || game.play(mv).is_err() {
+            continue;
+        }
+        let escaped = game.winner() == Some(Side::Defender);
+        let _ = game.undo();
+        if escaped {
+            return true;
+        }
+    }
+    false
+}
+
+/// Number of pieces a move captures, without keeping it on the board
+fn captures<G: Rules>(game: &mut G, mv: Move) -> usize {
+    match game.play(mv) {
+        Ok(captured) => {
+            let _ = game.undo();
+            captured.len()
+        }
+        Err(_) => 0,
+    }
+}
+
+/// Analyses every move played in the game, from its start position.
+pub fn analyze(game: &Game, weights: Weights, time_per_position: Duration) -> Analysis {
+    analyze_with_tablebase(game, weights, time_per_position, tablebase())
+}
+
+fn analyze_with_tablebase(game: &Game, weights: Weights, time_per_position: Duration, tablebase: Option<&Tablebase>) -> Analysis {
+    let moves = game.history();
+    let mut game = game.clone();
+    for _ in 0..moves.len() {
+        let _ = game.undo();
+    }
+
+    let limits = SearchLimits { max_depth: 32, time_limit: time_per_position, eval_noise: 0 };
+    let mut searcher = Searcher::new(weights);
+    let size = game.size();
+
+    // Best move and evaluation for the attackers of every position, including the final one
+    let mut evaluations = Vec::with_capacity(moves.len() + 1);
+    let mut escapes = Vec::with_capacity(moves.len());
+    for ply in 0..=moves.len() {
+        let evaluation = match game.winner() {
+            Some(Side::Attacker) => (None, WIN_SCORE),
+            Some(Side::Defender) => (None, -WIN_SCORE),
+            None => {
+                let result = searcher.search(&game, &limits);
+                let score = match game.side_to_move() {
+                    Side::Attacker => result.score,
+                    Side::Defender => -result.score,
+                };
+                (result.best_move, score)
+            }
+        };
+        evaluations.push(evaluation);
+
+        if let Some(&mv) = moves.get(ply) {
+            escapes.push(king_escape_available(&mut game));
+            if game.play(mv).is_err() {
+                break;
+            }
+        }
+    }
+    let winner = game.winner();
+
+    // Replay the game to judge each move against the engine's choice
+    for _ in 0..moves.len() {
+        let _ = game.undo();
+    }
+    let mut annotated = Vec::with_capacity(moves.len());
+    for (index, &mv) in moves.iter().enumerate() {
+        let (Some(&(best_move, before)), Some(&(_, after))) = (evaluations.get(index), evaluations.get(index + 1)) else {
+            break;
+        };
+        let side = game.side_to_move();
+        let for_mover = |score: i32| {
+            let score = score.clamp(-SCORE_CAP, SCORE_CAP);
+            if side == Side::Attacker { score } else { -score }
+        };
+        let (best, played) = (for_mover(before), for_mover(after));
+        let is_best = best_move == Some(mv);
+        let loss = if is_best { 0 } else { (best - played).max(0) };
+
+        let missed_capture = match best_move {
+            Some(best_move) if !is_best => captures(&mut game, best_move) > 0 && captures(&mut game, mv) == 0,
+            _ => false,
+        };
+        let missed_king_escape = escapes[index] && after != -WIN_SCORE;
+
+        annotated.push(AnnotatedMove {
+            ply: index + 1,
+            side,
+            notation: mv.notation(size),
+            best_move: best_move.filter(|_| !is_best).map(|best_move| best_move.notation(size)),
+            eval: after,
+            loss,
+            judgement: Judgement::from_loss(loss),
+            accuracy: if is_best { 100.0 } else { move_accuracy(best, played) },
+            missed_king_escape,
+            missed_capture,
+            tablebase: None,
+        });
+
+        if game.play(mv).is_err() {
+            break;
+        }
+        if let Some(entry) = annotated.last_mut() {
+            entry.tablebase = tablebase.and_then(|tablebase| tablebase.probe(&game));
+        }
+    }
+
+    let accuracy = |side: Side| {
+        let values: Vec<f64> = annotated.iter().filter(|entry| entry.side == side).map(|entry| entry.accuracy).collect();
+        (!values.is_empty()).then(|| values.iter().sum::<f64>() / values.len() as f64)
+    };
+
+    Analysis {
+        attacker_accuracy: accuracy(Side::Attacker),
+        defender_accuracy: accuracy(Side::Defender),
+        moves: annotated,
+        winner,
+    }
+}
+
+
+#[cfg(test)]
+#[cfg(feature = "brandubh")]
+mod tests {
+    use super::*;
+    use crate::engine::Variant;
+    use crate::tablebase::generate;
+
+    const TIME: Duration = Duration::from_millis(200);
+
+    fn game(position: &str, moves: &[&str]) -> Game {
+        let mut game = Game::from_position(Variant::Brandubh, position).unwrap();
+        for notation in moves {
+            game.play(Move::parse(notation, 7).unwrap()).unwrap();
+        }
+        game
+    }
+
+    #[test]
+    fn judgements_follow_the_loss_thresholds() {
+        for (loss, judgement) in [
+            (0, Judgement::Good),
+            (49, Judgement::Good),
+            (50, Judgement::Inaccuracy),
+            (119, Judgement::Inaccuracy),
+            (120, Judgement::Mistake),
+            (299, Judgement::Mistake),
+            (300, Judgement::Blunder),
+            (3_000, Judgement::Blunder),
+        ] {
+            assert_eq!(Judgement::from_loss(loss), judgement, "{}", loss);
+        }
+        let symbols: Vec<_> = [Judgement::Good, Judgement::Inaccuracy, Judgement::Mistake, Judgement::Blunder].iter().map(|judgement| judgement.symbol()).collect();
+        assert_eq!(symbols, ["", "?!", "?", "??"]);
+    }
+
+    #[test]
+    fn accuracy_falls_with_the_lost_winning_chances() {
+        assert_eq!(win_probability(0), 0.5);
+        assert!((move_accuracy(200, 200) - 100.0).abs() < 1e-9);
+        assert!((move_accuracy(0, 200) - 100.0).abs() < 1e-9);
+
+        // Losing 400 points from an even position drops the winning chances by 23 points
+        let drop = (win_probability(0) - win_probability(-400)) * 100.0;
+        let expected = 103.17 * (-0.04354 * drop).exp() - 3.17;
+        assert!((move_accuracy(0, -400) - expected).abs() < 1e-9);
+        assert!((34.0..35.0).contains(&expected), "{}", expected);
+
+        let accuracies: Vec<f64> = [0, -50, -120, -300, -1_500].iter().map(|&played| move_accuracy(0, played)).collect();
+        assert!(accuracies.windows(2).all(|pair| pair[0] > pair[1]), "{:?}", accuracies);
+        assert_eq!(move_accuracy(SCORE_CAP, -SCORE_CAP), 0.0);
+    }
+
+    #[test]
+    fn a_missed_escape_that_loses_the_king_is_a_blunder() {
+        // The king could step into a1 but walks into the attackers' trap instead
+        let analysis = analyze(&game("7/7/7/7/2a4/a6/1k5 d", &["b1-b2", "c3-c2"]), Weights::for_variant(Variant::Brandubh), TIME);
+        assert_eq!(analysis.winner, Some(Side::Attacker));
+        assert_eq!(analysis.moves.len(), 2);
+
+        let blunder = &analysis.moves[0];
+        assert_eq!((blunder.ply, blunder.side), (1, Side::Defender));
+        assert_eq!(blunder.judgement, Judgement::Blunder);
+        assert_eq!(blunder.best_move.as_deref(), Some("b1-a1"));
+        assert!(blunder.missed_king_escape);
+        assert!(!blunder.missed_capture);
+        assert!(blunder.accuracy < 1.0);
+
+        let capture = &analysis.moves[1];
+        assert_eq!(capture.judgement, Judgement::Good);
+        assert_eq!(capture.best_move, None);
+        assert_eq!(capture.accuracy, 100.0);
+        assert!(!capture.missed_capture);
+        assert_eq!(capture.eval, WIN_SCORE);
+
+        assert_eq!(analysis.attacker_accuracy, Some(100.0));
+        assert!(analysis.defender_accuracy.unwrap() < 1.0);
+        assert_eq!(analysis.annotated_record(), "1. b1-b2?? c3-c2");
+    }
+
+    #[test]
+    fn a_move_that_leaves_a_capture_on_the_board_is_flagged() {
+        // d1-d3 would take the defender on c3
+        let analysis = analyze(&game("7/7/7/3k3/1ad4/7/3a3 a", &["d1-e1"]), Weights::for_variant(Variant::Brandubh), TIME);
+        let played = &analysis.moves[0];
+        assert!(played.missed_capture);
+        assert!(!played.missed_king_escape);
+        assert_eq!(analysis.defender_accuracy, None);
+    }
+
+    #[test]
+    fn positions_covered_by_the_tablebase_are_annotated() {
+        let tablebase = generate(2, |_| {}).unwrap();
+        let game = game("7/7/7/7/7/k6/5a1 d", &["a2-a3"]);
+        let analysis = analyze_with_tablebase(&game, Weights::for_variant(Variant::Brandubh), TIME, Some(&tablebase));
+
+        // The king still reaches a corner after the attackers' reply
+        let played = &analysis.moves[0];
+        assert!(played.missed_king_escape);
+        assert_eq!(played.tablebase, Some(Outcome::Loss(2)));
+        assert_eq!(played.tablebase.unwrap().describe(Side::Attacker), "Defenders win in 2 plies");
+        assert_eq!(analyze(&game, Weights::for_variant(Variant::Brandubh), TIME).moves[0].tablebase, None);
+    }
+}
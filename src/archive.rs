@@ -0,0 +1,61 @@
+// Archive of finished games, one JSON record per line. The opening book and
+// other offline tools are rebuilt from it.
+
+use serde::{Deserialize, Serialize};
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+
+use crate::bot::BotConfig;
+use crate::engine::{Game, Rules, Side, Variant};
+
+/// Archive file the server appends finished games to
+pub const ARCHIVE_FILE: &str = "games.jsonl";
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct GameRecord {
+    pub variant: Variant,
+    pub moves: Vec<String>,             // Moves in notation, e.g. "d1-d3"
+    pub winner: Option<Side>,
+    pub computer: Option<BotConfig>,    // Settings of the computer opponent, if any
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub engines: Option<[BotConfig; 2]>, // Attacker and defender of an engine-versus-engine game
+}
+
+impl GameRecord {
+    pub fn new(game: &Game, computer: Option<BotConfig>) -> Self {
+        let size = game.size();
+        GameRecord {
+            variant: game.variant(),
+            moves: game.history().iter().map(|mv| mv.notation(size)).collect(),
+            winner: game.winner(),
+            computer,
+            engines: None,
+        }
+    }
+}
+
+/// Appends a record to the archive, creating the file if needed.
+pub fn append_record(path: &str, record: &GameRecord) -> Result<(), String> {
+    let line = serde_json::to_string(record).map_err(|error| error.to_string())?;
+    let mut file = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .map_err(|error| format!("Cannot open {}: {}", path, error))?;
+    writeln!(file, "{}", line).map_err(|error| format!("Cannot write {}: {}", path, error))
+}
+
+/// Reads every record of the archive. A missing file is an empty archive.
+pub fn read_records(path: &str) -> Result<Vec<GameRecord>, String> {
+    if !std::path::Path::new(path).exists() {
+        return Ok(Vec::new());
+    }
+    let text = fs::read_to_string(path).map_err(|error| format!("Cannot read {}: {}", path, error))?;
+    text.lines()
+        .enumerate()
+        .filter(|(_, line)| !line.trim().is_empty())
+        .map(|(index, line)| {
+            serde_json::from_str(line).map_err(|error| format!("{} line {}: {}", path, index + 1, error))
+        })
+        .collect()
+}
@@ -0,0 +1,209 @@
+// Opening book: how often each move was played from the early positions of
+// archived games and how those games ended, per variant and keyed by the
+// Zobrist hash of the position.
+
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Mutex, OnceLock};
+use std::thread;
+
+use crate::archive::GameRecord;
+use crate::bot::{create_bot, BotConfig, EngineKind};
+use crate::engine::{Game, Move, Rules, Side, Variant};
+
+/// Book file written by the book builder and loaded at startup
+pub const BOOK_FILE: &str = "book.json";
+
+/// Number of plies from the start of each game entered in the book
+pub const BOOK_PLIES: usize = 16;
+
+/// Self-play games reaching this length are recorded as draws
+const SELF_PLAY_MAX_PLIES: usize = 300;
+
+/// Level and thinking time of the bots playing self-play games for the book
+const SELF_PLAY_LEVEL: u8 = 5;
+const SELF_PLAY_THINK_TIME_MS: u64 = 100;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BookMove {
+    pub mv: Move,
+    pub games: u32,
+    pub attacker_wins: u32,
+    pub defender_wins: u32,
+}
+
+impl BookMove {
+    /// Share of the games won by a side, between 0 and 1
+    pub fn win_rate(&self, side: Side) -> f64 {
+        let wins = match side {
+            Side::Attacker => self.attacker_wins,
+            Side::Defender => self.defender_wins,
+        };
+        wins as f64 / self.games.max(1) as f64
+    }
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct OpeningBook {
+    positions: HashMap<Variant, HashMap<u64, Vec<BookMove>>>,
+}
+
+/// Book loaded at startup, consulted by the bots
+static BOOK: OnceLock<OpeningBook> = OnceLock::new();
+
+impl OpeningBook {
+    /// Enters the first `BOOK_PLIES` moves of a finished game. The whole
+    /// record is replayed first, so a game with an illegal move leaves the
+    /// book unchanged.
+    pub fn add_game(&mut self, record: &GameRecord) -> Result<(), String> {
+        let mut game = Game::new(record.variant);
+        let mut opening = Vec::new();
+        for notation in &record.moves {
+            let mv = Move::parse(notation, game.size())?;
+            if opening.len() < BOOK_PLIES {
+                opening.push((game.position_hash(), mv));
+            }
+            game.play(mv)?;
+        }
+
+        let positions = self.positions.entry(record.variant).or_default();
+        for (hash, mv) in opening {
+            let moves = positions.entry(hash).or_default();
+            let index = match moves.iter().position(|entry| entry.mv == mv) {
+                Some(index) => index,
+                None => {
+                    moves.push(BookMove { mv, games: 0, attacker_wins: 0, defender_wins: 0 });
+                    moves.len() - 1
+                }
+            };
+            let entry = &mut moves[index];
+            entry.games += 1;
+            match record.winner {
+                Some(Side::Attacker) => entry.attacker_wins += 1,
+                Some(Side::Defender) => entry.defender_wins += 1,
+                None => {}
+            }
+        }
+        Ok(())
+    }
+
+    /// Book moves from a position, most played first.
+    pub fn moves(&self, variant: Variant, hash: u64) -> Vec<BookMove> {
+        let mut moves = self
+            .positions
+            .get(&variant)
+            .and_then(|positions| positions.get(&hash))
+            .cloned()
+            .unwrap_or_default();
+        moves.sort_by_key(|entry| std::cmp::Reverse(entry.games));
+        moves
+    }
+
+    /// Number of positions in the book for a variant
+    pub fn position_count(&self, variant: Variant) -> usize {
+        self.positions.get(&variant).map_or(0, HashMap::len)
+    }
+
+    pub fn read(path: &str) -> Result<OpeningBook, String> {
+        let text = fs::read_to_string(path).map_err(|error| format!("Cannot read {}: {}", path, error))?;
+        serde_json::from_str(&text).map_err(|error| format!("Invalid book file {}: {}", path, error))
+    }
+
+    pub fn write(&self, path: &str) -> Result<(), String> {
+        let text = serde_json::to_string(self).map_err(|error| error.to_string())?;
+        fs::write(path, text).map_err(|error| format!("Cannot write {}: {}", path, error))
+    }
+}
+
+/// Loads the book used by the bots and the book pages, if the file exists,
+/// and returns the number of positions it holds.
+pub fn load_book(path: &str) -> Result<usize, String> {
+    if !std::path::Path::new(path).exists() {
+        return Ok(0);
+    }
+    let book = OpeningBook::read(path)?;
+    let count = Variant::ALL.iter().map(|&variant| book.position_count(variant)).sum();
+    BOOK.set(book).map_err(|_| "The opening book is already loaded".to_string())?;
+    Ok(count)
+}
+
+/// The book loaded at startup, if any
+pub fn book() -> Option<&'static OpeningBook> {
+    BOOK.get()
+}
+
+/// Plays games between two weakened bots, whose randomness gives varied
+/// openings, on all CPU cores.
+pub fn self_play_records(variant: Variant, games: usize) -> Vec<GameRecord> {
+    let next_game = AtomicUsize::new(0);
+    let records = Mutex::new(Vec::new());
+    let threads = thread::available_parallelism().map_or(1, |count| count.get()).min(games.max(1));
+
+    thread::scope(|scope| {
+        for _ in 0..threads {
+            scope.spawn(|| {
+                while next_game.fetch_add(1, Ordering::Relaxed) < games {
+                    let mut game = Game::new(variant);
+                    let mut bots = [Side::Attacker, Side::Defender].map(|side| {
+                        let mut config = BotConfig::new(side, EngineKind::AlphaBeta, SELF_PLAY_LEVEL);
+                        config.think_time_ms = SELF_PLAY_THINK_TIME_MS;
+                        create_bot(&config, variant)
+                    });
+                    for _ in 0..SELF_PLAY_MAX_PLIES {
+                        if game.winner().is_some() {
+                            break;
+                        }
+                        let [attacker, defender] = &mut bots;
+                        let bot = if game.side_to_move() == Side::Attacker { attacker } else { defender };
+                        match bot.choose_move(&game) {
+                            Some(mv) if game.play(mv).is_ok() => {}
+                            _ => break,
+                        }
+                    }
+                    records.lock().unwrap().push(GameRecord::new(&game, None));
+                }
+            });
+        }
+    });
+
+    records.into_inner().unwrap()
+}
+
+#[cfg(test)]
+#[cfg(feature = "brandubh")]
+mod tests {
+    use super::*;
+
+    fn record(moves: &[&str], winner: Option<Side>) -> GameRecord {
+        GameRecord {
+            variant: Variant::Brandubh,
+            moves: moves.iter().map(|mv| mv.to_string()).collect(),
+            winner,
+            computer: None,
+            engines: None,
+        }
+    }
+
+    #[test]
+    fn games_are_counted_from_each_position() {
+        let mut book = OpeningBook::default();
+        book.add_game(&record(&["d1-c1", "d3-c3"], Some(Side::Attacker))).unwrap();
+        book.add_game(&record(&["d1-c1", "d3-e3"], Some(Side::Defender))).unwrap();
+
+        let start = Game::new(Variant::Brandubh).position_hash();
+        let moves = book.moves(Variant::Brandubh, start);
+        assert_eq!(moves.len(), 1);
+        assert_eq!((moves[0].games, moves[0].attacker_wins, moves[0].defender_wins), (2, 1, 1));
+        assert_eq!(book.position_count(Variant::Brandubh), 2);
+    }
+
+    #[test]
+    fn invalid_records_leave_the_book_unchanged() {
+        let mut book = OpeningBook::default();
+        assert!(book.add_game(&record(&["d1-c1", "d3-c3", "a1-a2"], None)).is_err());
+        assert!(book.add_game(&record(&["d1-c1", "nonsense"], None)).is_err());
+        assert!(book.positions.is_empty());
+    }
+}
@@ -0,0 +1,545 @@
+// Computer opponents. Every engine implements `Bot`, so the server and the
+// command-line tools can use them interchangeably.
+
+use rand::rngs::StdRng;
+use rand::seq::SliceRandom;
+use rand::{Rng, SeedableRng};
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
+
+use crate::book::{book, BOOK_PLIES};
+use crate::engine::{Game, Move, Rules, Side, Variant};
+use crate::eval::{evaluate_for_attacker, Weights};
+use crate::external::{engine_names, ExternalEngine};
+use crate::mcts::{MctsLimits, MctsSearcher};
+use crate::search::{SearchLimits, Searcher};
+use crate::tablebase::{tablebase, Outcome};
+
+/// Thinking time for move hints
+pub const HINT_THINK_TIME_MS: u64 = 500;
+
+/// Difficulty levels, from beginner to full strength
+pub const MIN_LEVEL: u8 = 1;
+pub const MAX_LEVEL: u8 = 8;
+
+/// How a difficulty level weakens the engine
+struct LevelSettings {
+    max_depth: u32,        // Alpha-beta search depth
+    think_time_ms: u64,    // Time limit per move
+    eval_noise: i32,       // Largest random offset added to evaluations
+    blunder_chance: f64,   // Probability of playing a random move instead
+}
+
+const LEVELS: [LevelSettings; MAX_LEVEL as usize] = [
+    LevelSettings { max_depth: 1, think_time_ms: 200, eval_noise: 300, blunder_chance: 0.4 },
+    LevelSettings { max_depth: 1, think_time_ms: 300, eval_noise: 200, blunder_chance: 0.3 },
+    LevelSettings { max_depth: 2, think_time_ms: 400, eval_noise: 150, blunder_chance: 0.2 },
+    LevelSettings { max_depth: 2, think_time_ms: 600, eval_noise: 100, blunder_chance: 0.12 },
+    LevelSettings { max_depth: 3, think_time_ms: 800, eval_noise: 60, blunder_chance: 0.06 },
+    LevelSettings { max_depth: 4, think_time_ms: 1200, eval_noise: 30, blunder_chance: 0.03 },
+    LevelSettings { max_depth: 6, think_time_ms: 1600, eval_noise: 10, blunder_chance: 0.0 },
+    LevelSettings { max_depth: 32, think_time_ms: 2000, eval_noise: 0, blunder_chance: 0.0 },
+];
+
+fn level_settings(level: u8) -> &'static LevelSettings {
+    &LEVELS[(level.clamp(MIN_LEVEL, MAX_LEVEL) - MIN_LEVEL) as usize]
+}
+
+/// Default Monte Carlo settings: playouts per move and UCT exploration constant
+pub const DEFAULT_PLAYOUTS: u32 = 20_000;
+pub const DEFAULT_EXPLORATION: f64 = std::f64::consts::SQRT_2;
+
+/// Largest Monte Carlo settings accepted in an engine spec
+pub const MAX_PLAYOUTS: u32 = 1_000_000;
+pub const MAX_EXPLORATION: f64 = 10.0;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum EngineKind {
+    AlphaBeta,
+    Mcts { playouts: u32, exploration: f64 },
+    External { name: String },  // Engine from the engines file, as "external:<name>"
+}
+
+impl EngineKind {
+    /// Looks up an engine by the spec used in forms and on the command line,
+    /// see `parse`.
+    pub fn from_name(name: &str) -> Option<EngineKind> {
+        EngineKind::parse(name).ok()
+    }
+
+    /// Parses an engine spec: "alphabeta", "mcts", Monte Carlo with its
+    /// playouts per move and exploration constant, e.g.
+    /// "mcts:playouts=5000,c=0.8", or "external:<name>".
+    pub fn parse(spec: &str) -> Result<EngineKind, String> {
+        match spec.split_once(':') {
+            None if spec == "alphabeta" => Ok(EngineKind::AlphaBeta),
+            None if spec == "mcts" => parse_mcts_settings(""),
+            Some(("mcts", settings)) => parse_mcts_settings(settings),
+            Some(("external", name)) if engine_names().iter().any(|engine| engine == name) => {
+                Ok(EngineKind::External { name: name.to_string() })
+            }
+            _ => Err(format!("Unknown engine: {}", spec)),
+        }
+    }
+}
+
+/// Reads comma-separated Monte Carlo settings, "playouts=<count>" and
+/// "c=<exploration>", each defaulting when left out.
+fn parse_mcts_settings(settings: &str) -> Result<EngineKind, String> {
+    let mut playouts = DEFAULT_PLAYOUTS;
+    let mut exploration = DEFAULT_EXPLORATION;
+    for setting in settings.split(',').filter(|setting| !setting.is_empty()) {
+        let (key, value) = setting
+            .split_once('=')
+            .ok_or_else(|| format!("Expected key=value in the MCTS settings: {}", setting))?;
+        match key {
+            "playouts" => {
+                playouts = value
+                    .parse()
+                    .ok()
+                    .filter(|playouts| (1..=MAX_PLAYOUTS).contains(playouts))
+                    .ok_or_else(|| format!("Playouts go from 1 to {}: {}", MAX_PLAYOUTS, value))?;
+            }
+            "c" => {
+                exploration = value
+                    .parse()
+                    .ok()
+                    .filter(|&c: &f64| c > 0.0 && c <= MAX_EXPLORATION)
+                    .ok_or_else(|| format!("The exploration constant must be above 0 and at most {}: {}", MAX_EXPLORATION, value))?;
+            }
+            _ => return Err(format!("Unknown MCTS setting: {} (expected playouts or c)", key)),
+        }
+    }
+    Ok(EngineKind::Mcts { playouts, exploration })
+}
+
+/// Settings for a computer opponent
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct BotConfig {
+    pub side: Side,          // Side played by the computer
+    pub engine: EngineKind,  // Search algorithm
+    pub level: u8,           // Difficulty level, MIN_LEVEL to MAX_LEVEL
+    pub think_time_ms: u64,  // Time limit per move
+}
+
+impl BotConfig {
+    pub fn new(side: Side, engine: EngineKind, level: u8) -> Self {
+        let level = level.clamp(MIN_LEVEL, MAX_LEVEL);
+        BotConfig {
+            side,
+            engine,
+            level,
+            think_time_ms: level_settings(level).think_time_ms,
+        }
+    }
+}
+
+/// A computer player that picks moves for the side to move.
+pub trait Bot: Send {
+    /// Chooses a move, or None if the side to move has no legal move.
+    fn choose_move(&mut self, game: &Game) -> Option<Move>;
+
+    /// Messages for the log from choosing moves since the last call, e.g.
+    /// an external engine failing.
+    fn take_messages(&mut self) -> Vec<String> {
+        Vec::new()
+    }
+}
+
+/// Iterative-deepening alpha-beta search with the default evaluation
+pub struct AlphaBetaBot {
+    searcher: Searcher,
+    limits: SearchLimits,
+}
+
+impl AlphaBetaBot {
+    pub fn new(weights: Weights, limits: SearchLimits) -> Self {
+        AlphaBetaBot {
+            searcher: Searcher::new(weights),
+            limits,
+        }
+    }
+}
+
+impl Bot for AlphaBetaBot {
+    fn choose_move(&mut self, game: &Game) -> Option<Move> {
+        self.searcher.search(game, &self.limits).best_move
+    }
+}
+
+/// Monte Carlo tree search with random playouts
+pub struct MctsBot {
+    searcher: MctsSearcher,
+    limits: MctsLimits,
+}
+
+impl MctsBot {
+    pub fn new(weights: Weights, limits: MctsLimits) -> Self {
+        MctsBot {
+            searcher: MctsSearcher::new(weights),
+            limits,
+        }
+    }
+}
+
+impl Bot for MctsBot {
+    fn choose_move(&mut self, game: &Game) -> Option<Move> {
+        self.searcher.search(game, &self.limits)
+    }
+}
+
+/// Plays a uniformly random legal move
+pub struct RandomBot {
+    rng: StdRng,
+}
+
+impl Default for RandomBot {
+    fn default() -> Self {
+        RandomBot { rng: StdRng::from_entropy() }
+    }
+}
+
+impl Bot for RandomBot {
+    fn choose_move(&mut self, game: &Game) -> Option<Move> {
+        game.legal_moves().choose(&mut self.rng).copied()
+    }
+}
+
+/// Plays the move with the best static evaluation one ply ahead, taking a
+/// win whenever there is one; ties are broken at random.
+pub struct GreedyBot {
+    weights: Weights,
+    rng: StdRng,
+}
+
+impl GreedyBot {
+    pub fn new(weights: Weights) -> Self {
+        GreedyBot { weights, rng: StdRng::from_entropy() }
+    }
+}
+
+impl Bot for GreedyBot {
+    fn choose_move(&mut self, game: &Game) -> Option<Move> {
+        let side = game.side_to_move();
+        let mut game = game.clone();
+        let mut best = Vec::new();
+        let mut best_score = i32::MIN;
+        for mv in game.legal_moves() {
+            if game.play(mv).is_err() {
+                continue;
+            }
+            let score = evaluate_for_attacker(&game, &self.weights);
+            let _ = game.undo();
+            let score = if side == Side::Attacker { score } else { -score };
+            if score > best_score {
+                best_score = score;
+                best.clear();
+            }
+            if score == best_score {
+                best.push(mv);
+            }
+        }
+        best.choose(&mut self.rng).copied()
+    }
+}
+
+/// Engine running as a subprocess. When it crashes, times out or plays an
+/// illegal move, its process is stopped and the fallback bot moves instead.
+pub struct ExternalBot {
+    name: String,
+    time_limit: Duration,
+    fallback: Box<dyn Bot>,
+    messages: Vec<String>,
+}
+
+impl ExternalBot {
+    pub fn new(name: String, time_limit: Duration, fallback: Box<dyn Bot>) -> Self {
+        ExternalBot { name, time_limit, fallback, messages: Vec::new() }
+    }
+}
+
+impl Bot for ExternalBot {
+    fn choose_move(&mut self, game: &Game) -> Option<Move> {
+        let result = ExternalEngine::acquire(&self.name).and_then(|mut engine| {
+            let result = engine.request_move(game, self.time_limit);
+            self.messages.extend(engine.take_messages());
+            if result.is_ok() {
+                engine.release();
+            }
+            result
+        });
+        match result {
+            Ok(mv) => Some(mv),
+            Err(error) => {
+                self.messages.push(format!("External engine failed, using the built-in engine: {}", error));
+                self.fallback.choose_move(game)
+            }
+        }
+    }
+
+    fn take_messages(&mut self) -> Vec<String> {
+        std::mem::take(&mut self.messages)
+    }
+}
+
+/// Builds the full-strength bot used to suggest moves to players.
+pub fn hint_bot(variant: Variant) -> Box<dyn Bot> {
+    Box::new(TablebaseBot::new(Box::new(AlphaBetaBot::new(
+        Weights::for_variant(variant),
+        SearchLimits {
+            max_depth: 32,
+            time_limit: Duration::from_millis(HINT_THINK_TIME_MS),
+            eval_noise: 0,
+        },
+    ))))
+}
+
+/// Book moves are only played once they were seen in this many games
+const MIN_BOOK_GAMES: u32 = 2;
+
+/// Wraps another bot and plays from the opening book while the game is in it.
+pub struct BookBot {
+    inner: Box<dyn Bot>,
+    variant: Variant,
+    rng: StdRng,
+}
+
+impl BookBot {
+    pub fn new(inner: Box<dyn Bot>, variant: Variant) -> Self {
+        BookBot {
+            inner,
+            variant,
+            rng: StdRng::from_entropy(),
+        }
+    }
+}
+
+impl Bot for BookBot {
+    fn choose_move(&mut self, game: &Game) -> Option<Move> {
+        if let Some(book) = book().filter(|_| game.history().len() < BOOK_PLIES) {
+            let side = game.side_to_move();
+            let moves: Vec<_> = book
+                .moves(self.variant, game.position_hash())
+                .into_iter()
+                .filter(|entry| entry.games >= MIN_BOOK_GAMES)
+                .collect();
+            // Pick moves in proportion to how often they were played and how
+            // well they scored for the side to move
+            if let Ok(entry) = moves.choose_weighted(&mut self.rng, |entry| {
+                entry.games as f64 * (entry.win_rate(side) + 0.1)
+            }) {
+                return Some(entry.mv);
+            }
+        }
+        self.inner.choose_move(game)
+    }
+
+    fn take_messages(&mut self) -> Vec<String> {
+        self.inner.take_messages()
+    }
+}
+
+/// Wraps another bot and plays perfectly once the position is in the
+/// endgame tablebase.
+pub struct TablebaseBot {
+    inner: Box<dyn Bot>,
+}
+
+impl TablebaseBot {
+    pub fn new(inner: Box<dyn Bot>) -> Self {
+        TablebaseBot { inner }
+    }
+}
+
+impl Bot for TablebaseBot {
+    fn choose_move(&mut self, game: &Game) -> Option<Move> {
+        let Some(outcomes) = tablebase().and_then(|tablebase| tablebase.move_outcomes(game)) else {
+            return self.inner.choose_move(game);
+        };
+        let best = outcomes.iter().copied().max_by_key(|&(_, outcome)| outcome.rank())?;
+        // Among drawing moves, let the engine pick one that keeps some pressure
+        if best.1 == Outcome::Draw {
+            let choice = self.inner.choose_move(game);
+            if outcomes.iter().any(|&(mv, outcome)| Some(mv) == choice && outcome == Outcome::Draw) {
+                return choice;
+            }
+        }
+        Some(best.0)
+    }
+
+    fn take_messages(&mut self) -> Vec<String> {
+        self.inner.take_messages()
+    }
+}
+
+/// Wraps another bot and sometimes plays a random move instead of its choice.
+pub struct BlunderingBot {
+    inner: Box<dyn Bot>,
+    blunder_chance: f64,
+    rng: StdRng,
+}
+
+impl BlunderingBot {
+    pub fn new(inner: Box<dyn Bot>, blunder_chance: f64) -> Self {
+        BlunderingBot {
+            inner,
+            blunder_chance,
+            rng: StdRng::from_entropy(),
+        }
+    }
+}
+
+impl Bot for BlunderingBot {
+    fn choose_move(&mut self, game: &Game) -> Option<Move> {
+        if self.rng.gen_bool(self.blunder_chance) {
+            if let Some(&mv) = game.legal_moves().choose(&mut self.rng) {
+                return Some(mv);
+            }
+        }
+        self.inner.choose_move(game)
+    }
+
+    fn take_messages(&mut self) -> Vec<String> {
+        self.inner.take_messages()
+    }
+}
+
+/// Builds the bot described by a configuration for a variant, weakened to
+/// its level. External engines play at their own strength, with the
+/// level's thinking time.
+pub fn create_bot(config: &BotConfig, variant: Variant) -> Box<dyn Bot> {
+    let settings = level_settings(config.level);
+    let time_limit = Duration::from_millis(config.think_time_ms);
+    let alpha_beta = || AlphaBetaBot::new(
+        Weights::for_variant(variant),
+        SearchLimits { max_depth: settings.max_depth, time_limit, eval_noise: settings.eval_noise },
+    );
+    let engine: Box<dyn Bot> = match &config.engine {
+        EngineKind::AlphaBeta => Box::new(alpha_beta()),
+        &EngineKind::Mcts { playouts, exploration } => Box::new(MctsBot::new(
+            Weights::for_variant(variant),
+            MctsLimits {
+                // Fewer playouts give noisier statistics at lower levels
+                playouts: (playouts / MAX_LEVEL as u32 * config.level as u32).max(1),
+                exploration,
+                time_limit,
+            },
+        )),
+        EngineKind::External { name } => {
+            return Box::new(ExternalBot::new(name.clone(), time_limit, Box::new(alpha_beta())));
+        }
+    };
+    let bot = Box::new(BookBot::new(Box::new(TablebaseBot::new(engine)), variant));
+
+    if settings.blunder_chance > 0.0 {
+        Box::new(BlunderingBot::new(bot, settings.blunder_chance))
+    } else {
+        bot
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn engine_specs_parse_with_mcts_settings() {
+        assert_eq!(EngineKind::parse("alphabeta"), Ok(EngineKind::AlphaBeta));
+        assert_eq!(
+            EngineKind::parse("mcts"),
+            Ok(EngineKind::Mcts { playouts: DEFAULT_PLAYOUTS, exploration: DEFAULT_EXPLORATION })
+        );
+        assert_eq!(
+            EngineKind::parse("mcts:playouts=5000,c=0.8"),
+            Ok(EngineKind::Mcts { playouts: 5000, exploration: 0.8 })
+        );
+        assert_eq!(
+            EngineKind::parse("mcts:c=2"),
+            Ok(EngineKind::Mcts { playouts: DEFAULT_PLAYOUTS, exploration: 2.0 })
+        );
+    }
+
+    #[test]
+    fn invalid_engine_specs_are_rejected() {
+        for spec in [
+            "minimax",
+            "mcts:playouts=0",
+            "mcts:playouts=2000000",
+            "mcts:playouts=many",
+            "mcts:c=0",
+            "mcts:c=-1",
+            "mcts:c=NaN",
+            "mcts:c=11",
+            "mcts:depth=3",
+            "mcts:playouts",
+            "external:missing",
+        ] {
+            assert!(EngineKind::parse(spec).is_err(), "{} should be rejected", spec);
+        }
+    }
+
+    #[test]
+    fn levels_get_stronger_and_are_clamped() {
+        for pair in LEVELS.windows(2) {
+            let (weaker, stronger) = (&pair[0], &pair[1]);
+            assert!(weaker.max_depth <= stronger.max_depth);
+            assert!(weaker.think_time_ms <= stronger.think_time_ms);
+            assert!(weaker.eval_noise >= stronger.eval_noise);
+            assert!(weaker.blunder_chance >= stronger.blunder_chance);
+        }
+        assert_eq!(LEVELS[MAX_LEVEL as usize - 1].eval_noise, 0);
+        assert_eq!(LEVELS[MAX_LEVEL as usize - 1].blunder_chance, 0.0);
+
+        assert_eq!(BotConfig::new(Side::Attacker, EngineKind::AlphaBeta, 0).level, MIN_LEVEL);
+        assert_eq!(BotConfig::new(Side::Attacker, EngineKind::AlphaBeta, 99).level, MAX_LEVEL);
+        let config = BotConfig::new(Side::Attacker, EngineKind::AlphaBeta, 3);
+        assert_eq!(config.think_time_ms, LEVELS[2].think_time_ms);
+    }
+
+    #[test]
+    #[cfg(feature = "brandubh")]
+    fn bots_take_a_king_capture_at_full_strength() {
+        let game = Game::from_position(Variant::Brandubh, "7/7/1ak4/7/7/5d1/3a3 a").unwrap();
+        let capture = Move::parse("d1-d5", 7).ok();
+        for engine in [EngineKind::AlphaBeta, EngineKind::Mcts { playouts: 2_000, exploration: DEFAULT_EXPLORATION }] {
+            let mut config = BotConfig::new(Side::Attacker, engine, MAX_LEVEL);
+            config.think_time_ms = 2_000;
+            assert_eq!(create_bot(&config, Variant::Brandubh).choose_move(&game), capture);
+        }
+        assert_eq!(GreedyBot::new(Weights::for_variant(Variant::Brandubh)).choose_move(&game), capture);
+    }
+
+    #[test]
+    #[cfg(feature = "brandubh")]
+    fn blundering_bots_still_play_legal_moves() {
+        let game = Game::new(Variant::Brandubh);
+        let legal = game.legal_moves();
+        let mut always = BlunderingBot::new(Box::new(RandomBot::default()), 1.0);
+        let mut never = BlunderingBot::new(Box::new(GreedyBot::new(Weights::for_variant(Variant::Brandubh))), 0.0);
+        for _ in 0..20 {
+            assert!(legal.contains(&always.choose_move(&game).unwrap()));
+            assert!(legal.contains(&never.choose_move(&game).unwrap()));
+        }
+
+        // The weakest level still plays, whatever it rolls
+        let config = BotConfig::new(Side::Attacker, EngineKind::AlphaBeta, MIN_LEVEL);
+        let mut bot = create_bot(&config, Variant::Brandubh);
+        for _ in 0..5 {
+            assert!(legal.contains(&bot.choose_move(&game).unwrap()));
+        }
+    }
+
+    #[test]
+    #[cfg(feature = "brandubh")]
+    fn failing_external_engines_fall_back_and_report_it() {
+        let game = Game::new(Variant::Brandubh);
+        let bot = ExternalBot::new("missing".to_string(), Duration::from_millis(100), Box::new(RandomBot::default()));
+        let mut bot = BlunderingBot::new(Box::new(bot), 0.0);
+        assert!(game.legal_moves().contains(&bot.choose_move(&game).unwrap()));
+        let messages = bot.take_messages();
+        assert_eq!(messages.len(), 1);
+        assert!(messages[0].contains("Unknown external engine: missing"), "{}", messages[0]);
+        assert!(bot.take_messages().is_empty());
+    }
+}
@@ -0,0 +1,433 @@
+// Command-line subcommands. Running the binary without arguments starts the
+// web server; any arguments are handled here instead.
+
+use std::collections::HashMap;
+use std::time::Instant;
+
+use crate::engine::{position_string, Game, Move, Rules, Variant};
+use crate::perft::{divide, perft, self_check};
+#[cfg(feature = "bots")]
+use crate::{
+    archive::{append_record, read_records, ARCHIVE_FILE},
+    book::{self_play_records, OpeningBook, BOOK_FILE},
+    engine::Side,
+    eval::{read_weights_file, write_weights_file, Weights, WEIGHTS_FILE},
+    simulate::{simulate, write_dataset, Policy, SimulationConfig, SimulationStats, DEFAULT_MAX_PLIES},
+    tablebase::{generate, DEFAULT_PIECES, TABLEBASE_FILE},
+    tournament::{play_match, Entrant, TOURNAMENT_FILE},
+    tune::{error, self_play, tune},
+};
+
+const USAGE: &str = "Usage:
+  hnefatafl                      Start the web server on port 3030
+  hnefatafl perft <variant> <depth> [--position <position>] [--moves <moves>] [--divide] [--check]
+                                 Count leaf positions to the given depth, from the
+                                 start or a given position after the listed moves.
+                                 --divide lists the count below each root move,
+                                 --check also verifies make/unmake and hashing.";
+
+/// Usage of the subcommands built on the bots
+#[cfg(feature = "bots")]
+const BOTS_USAGE: &str = "  hnefatafl tune <variant> [--games <count>] [--output <file>]
+                                 Tune the evaluation weights of a variant on quiet
+                                 positions from self-play games (default 200) and
+                                 save them to the weights file (default weights.json),
+                                 which the bots load at startup.
+  hnefatafl book [--archive <file>] [--self-play <games>] [--variant <variant>] [--output <file>]
+                                 Rebuild the opening book (default book.json) from the
+                                 archived games (default games.jsonl), adding the given
+                                 number of self-play games for each variant, or only
+                                 for --variant.
+  hnefatafl tablebase [--pieces <count>] [--output <file>]
+                                 Solve every Brandubh position with up to the given
+                                 number of pieces, king included (default 3, at most 4)
+                                 and save the tablebase (default brandubh.tb), which the
+                                 bots and the analysis load at startup. Four pieces take
+                                 several minutes and a few GB of memory.
+  hnefatafl tournament <variant> <engine> <engine> [--games <count>] [--time <ms>] [--output <file>]
+                                 Play a match between two engines (default 20 games,
+                                 1000 ms per move), alternating sides from shared random
+                                 openings, report the score and Elo difference of the
+                                 first engine and save the games (default tournament.jsonl).
+  hnefatafl simulate [--variant <variant>] [--games <count>] [--attacker <policy>] [--defender <policy>]
+                     [--time <ms>] [--max-plies <count>] [--format json|csv] [--output <file>] [--dataset <file>]
+                                 Play games between two policies on all CPU cores (default
+                                 1000 games of every variant, random against random) and
+                                 print win rates, game length, captures and how the games
+                                 ended, as JSON or CSV. --dataset also saves every position
+                                 with the result of its game as CSV.";
+#[cfg(feature = "bots")]
+const BOTS_NOTES: &str = "Engines: alphabeta, mcts or external:<name>, optionally with a level, e.g. mcts/5 (default 8)
+         mcts takes its playouts per move (default 20000, at most 1000000) and exploration constant
+         (default 1.414, above 0 and at most 10), e.g. mcts:playouts=5000,c=0.8/5
+Policies: random, greedy (best evaluation one move ahead) or an engine";
+
+/// Usage of the subcommands compiled into this build, listing its variants
+fn usage() -> String {
+    let mut usage = USAGE.to_string();
+    #[cfg(feature = "bots")]
+    {
+        usage.push('\n');
+        usage.push_str(BOTS_USAGE);
+    }
+    let variants: Vec<&str> = Variant::ALL.iter().map(Variant::name).collect();
+    usage.push_str(&format!("\n\nVariants: {}", variants.join(", ")));
+    #[cfg(feature = "bots")]
+    {
+        usage.push('\n');
+        usage.push_str(BOTS_NOTES);
+    }
+    usage.push_str("\nPositions use the format printed by perft, e.g. \"3a3/3a3/3d3/aadkdaa/3d3/3a3/3a3 a\"");
+    usage
+}
+
+/// Runs a subcommand and returns the process exit code.
+pub fn run(args: &[String]) -> i32 {
+    let result = match args[0].as_str() {
+        "perft" => perft_command(&args[1..]),
+        #[cfg(feature = "bots")]
+        "tune" => tune_command(&args[1..]),
+        #[cfg(feature = "bots")]
+        "book" => book_command(&args[1..]),
+        #[cfg(feature = "bots")]
+        "tablebase" => tablebase_command(&args[1..]),
+        #[cfg(feature = "bots")]
+        "tournament" => tournament_command(&args[1..]),
+        #[cfg(feature = "bots")]
+        "simulate" => simulate_command(&args[1..]),
+        "help" | "--help" | "-h" => {
+            println!("{}", usage());
+            Ok(())
+        }
+        other => Err(format!("Unknown command: {}", other)),
+    };
+
+    match result {
+        Ok(()) => 0,
+        Err(error) => {
+            eprintln!("Error: {}\n\n{}", error, usage());
+            2
+        }
+    }
+}
+
+/// Loads the data files of the bots that exist in the working directory.
+/// Only the server and the subcommands whose bots play need them.
+#[cfg(feature = "bots")]
+pub fn load_bot_data() {
+    // Tuned evaluation weights, if the tuning tool has written them
+    match crate::eval::load_tuned_weights(crate::eval::WEIGHTS_FILE) {
+        Ok(0) => {}
+        Ok(count) => eprintln!("Loaded tuned weights for {} variants from {}", count, crate::eval::WEIGHTS_FILE),
+        Err(error) => eprintln!("Using the default weights: {}", error),
+    }
+
+    // Opening book for the bots and the book pages, if it has been built
+    match crate::book::load_book(crate::book::BOOK_FILE) {
+        Ok(0) => {}
+        Ok(count) => eprintln!("Loaded an opening book of {} positions from {}", count, crate::book::BOOK_FILE),
+        Err(error) => eprintln!("Playing without an opening book: {}", error),
+    }
+
+    // External engines offered as computer opponents, if any are configured
+    match crate::external::load_engines(crate::external::ENGINES_FILE) {
+        Ok(0) => {}
+        Ok(count) => eprintln!("Loaded {} external engines from {}", count, crate::external::ENGINES_FILE),
+        Err(error) => eprintln!("Playing without external engines: {}", error),
+    }
+
+    // Brandubh endgame tablebase, if it has been generated
+    match crate::tablebase::load_tablebase(crate::tablebase::TABLEBASE_FILE) {
+        Ok(0) => {}
+        Ok(pieces) => eprintln!("Loaded the Brandubh tablebase of up to {} pieces from {}", pieces, crate::tablebase::TABLEBASE_FILE),
+        Err(error) => eprintln!("Playing without a tablebase: {}", error),
+    }
+}
+
+/// Splits arguments into positional values and `--name [value]` options.
+/// Options listed in `with_values` take the next argument as their value,
+/// all others are flags stored with an empty value.
+fn parse_options(args: &[String], with_values: &[&str]) -> Result<(Vec<String>, HashMap<String, String>), String> {
+    let mut positional = Vec::new();
+    let mut options = HashMap::new();
+    let mut iter = args.iter();
+
+    while let Some(arg) = iter.next() {
+        if let Some(name) = arg.strip_prefix("--") {
+            let value = if with_values.contains(&name) {
+                iter.next().ok_or_else(|| format!("Missing value for --{}", name))?.clone()
+            } else {
+                String::new()
+            };
+            options.insert(name.to_string(), value);
+        } else {
+            positional.push(arg.clone());
+        }
+    }
+
+    Ok((positional, options))
+}
+
+fn parse_variant(name: Option<&String>) -> Result<Variant, String> {
+    let name = name.ok_or("Missing variant")?;
+    Variant::from_name(name).ok_or_else(|| format!("Unknown variant: {}", name))
+}
+
+/// Starts a game of the variant, optionally from a `--position` option and
+/// then playing the space-separated moves of a `--moves` option.
+fn setup_game(variant: Variant, options: &HashMap<String, String>) -> Result<Game, String> {
+    let mut game = match options.get("position") {
+        Some(position) => Game::from_position(variant, position)?,
+        None => Game::new(variant),
+    };
+    if let Some(moves) = options.get("moves") {
+        for notation in moves.split_whitespace() {
+            let mv = Move::parse(notation, game.size())?;
+            game.play(mv)?;
+        }
+    }
+    Ok(game)
+}
+
+fn perft_command(args: &[String]) -> Result<(), String> {
+    let (positional, options) = parse_options(args, &["position", "moves"])?;
+    let variant = parse_variant(positional.first())?;
+    let depth: u32 = positional
+        .get(1)
+        .ok_or("Missing depth")?
+        .parse()
+        .map_err(|_| "Depth must be a non-negative number".to_string())?;
+    let mut game = setup_game(variant, &options)?;
+
+    println!("{} {}", variant, position_string(&game));
+    let start = Instant::now();
+
+    let nodes = if options.contains_key("check") {
+        self_check(&mut game, depth)?
+    } else if options.contains_key("divide") {
+        let size = game.size();
+        let counts = divide(&mut game, depth);
+        for (mv, count) in &counts {
+            println!("{}: {}", mv.notation(size), count);
+        }
+        println!("Moves: {}", counts.len());
+        counts.iter().map(|(_, count)| count).sum()
+    } else {
+        perft(&mut game, depth)
+    };
+
+    let elapsed = start.elapsed().as_secs_f64();
+    println!("Nodes: {}", nodes);
+    println!("Time: {:.3}s ({:.0} nodes/s)", elapsed, nodes as f64 / elapsed.max(1e-9));
+    Ok(())
+}
+
+#[cfg(feature = "bots")]
+fn tune_command(args: &[String]) -> Result<(), String> {
+    let (positional, options) = parse_options(args, &["games", "output"])?;
+    let variant = parse_variant(positional.first())?;
+    let games: usize = match options.get("games") {
+        Some(games) => games.parse().map_err(|_| "The number of games must be a positive number".to_string())?,
+        None => 200,
+    };
+    let output = options.get("output").map_or(WEIGHTS_FILE, String::as_str);
+
+    let initial = Weights::for_variant(variant);
+    let start = Instant::now();
+    println!("Playing {} self-play games of {}...", games, variant);
+    let samples = self_play(variant, &initial, games);
+    println!("Collected {} quiet positions in {:.1}s", samples.len(), start.elapsed().as_secs_f64());
+    if samples.is_empty() {
+        return Err("No quiet positions to tune on".to_string());
+    }
+
+    println!("Initial error: {:.6}", error(&samples, &initial.values()));
+    let tuned = tune(&samples, &initial, |step, error| println!("Step {}: error {:.6}", step, error));
+    println!("{:#?}", tuned);
+
+    // Keep the weights of the other variants already in the file
+    let mut weights = if std::path::Path::new(output).exists() {
+        read_weights_file(output)?
+    } else {
+        HashMap::new()
+    };
+    weights.insert(variant, tuned);
+    write_weights_file(output, &weights)?;
+    println!("Saved the {} weights to {}", variant, output);
+    Ok(())
+}
+
+#[cfg(feature = "bots")]
+fn book_command(args: &[String]) -> Result<(), String> {
+    let (_, options) = parse_options(args, &["archive", "self-play", "variant", "output"])?;
+    let archive = options.get("archive").map_or(ARCHIVE_FILE, String::as_str);
+    let output = options.get("output").map_or(BOOK_FILE, String::as_str);
+    let self_play_games: usize = match options.get("self-play") {
+        Some(games) => games.parse().map_err(|_| "The number of self-play games must be a positive number".to_string())?,
+        None => 0,
+    };
+    let variants = match options.get("variant") {
+        Some(name) => vec![parse_variant(Some(name))?],
+        None => Variant::ALL.to_vec(),
+    };
+
+    let mut records = read_records(archive)?;
+    println!("Read {} archived games from {}", records.len(), archive);
+    if self_play_games > 0 {
+        for &variant in &variants {
+            let start = Instant::now();
+            records.extend(self_play_records(variant, self_play_games));
+            println!("Played {} self-play games of {} in {:.1}s", self_play_games, variant, start.elapsed().as_secs_f64());
+        }
+    }
+
+    let mut book = OpeningBook::default();
+    let mut skipped = 0;
+    for record in &records {
+        if let Err(error) = book.add_game(record) {
+            println!("Skipping a {} game: {}", record.variant, error);
+            skipped += 1;
+        }
+    }
+    for &variant in Variant::ALL {
+        println!("{}: {} positions", variant, book.position_count(variant));
+    }
+    book.write(output)?;
+    println!("Saved the book of {} games to {}", records.len() - skipped, output);
+    Ok(())
+}
+
+#[cfg(feature = "bots")]
+fn tablebase_command(args: &[String]) -> Result<(), String> {
+    let (_, options) = parse_options(args, &["pieces", "output"])?;
+    let output = options.get("output").map_or(TABLEBASE_FILE, String::as_str);
+    let pieces: usize = match options.get("pieces") {
+        Some(pieces) => pieces.parse().map_err(|_| "The number of pieces must be a positive number".to_string())?,
+        None => DEFAULT_PIECES,
+    };
+
+    let start = Instant::now();
+    let tablebase = generate(pieces, |message| println!("{}", message))?;
+    println!("Solved positions of up to {} pieces in {:.1}s", pieces, start.elapsed().as_secs_f64());
+    tablebase.write(output)?;
+    println!("Saved the tablebase to {}", output);
+    Ok(())
+}
+
+#[cfg(feature = "bots")]
+fn tournament_command(args: &[String]) -> Result<(), String> {
+    let (positional, options) = parse_options(args, &["games", "time", "output"])?;
+    let variant = parse_variant(positional.first())?;
+    load_bot_data();
+    let first = Entrant::parse(positional.get(1).ok_or("Missing engine")?)?;
+    let second = Entrant::parse(positional.get(2).ok_or("Missing engine")?)?;
+    let games: usize = match options.get("games") {
+        Some(games) => games.parse().map_err(|_| "The number of games must be a positive number".to_string())?,
+        None => 20,
+    };
+    let think_time_ms: u64 = match options.get("time") {
+        Some(time) => time.parse().map_err(|_| "The time per move must be a number of milliseconds".to_string())?,
+        None => 1000,
+    };
+    let output = options.get("output").map_or(TOURNAMENT_FILE, String::as_str);
+    let (first_name, second_name) = (&positional[1], &positional[2]);
+
+    println!("{} games of {}: {} against {}, {} ms per move", games, variant, first_name, second_name, think_time_ms);
+    let mut save_error = None;
+    let result = play_match(variant, [&first, &second], games, think_time_ms, |index, record, result| {
+        let outcome = match record.winner {
+            Some(side) => format!("{} win", side),
+            None => "draw".to_string(),
+        };
+        println!(
+            "Game {}: {} attacking, {} in {} plies ({}-{}-{})",
+            index,
+            if index % 2 == 1 { first_name } else { second_name },
+            outcome,
+            record.moves.len(),
+            result.wins,
+            result.draws,
+            result.losses
+        );
+        if let Err(error) = append_record(output, record) {
+            save_error.get_or_insert(error);
+        }
+    });
+
+    println!("{}: {} wins, {} draws, {} losses", first_name, result.wins, result.draws, result.losses);
+    println!("Score: {:.1}%", result.score() * 100.0);
+    println!("Average length: {:.1} plies", result.average_length());
+    match result.elo_difference() {
+        Some((elo, margin)) => println!("Elo difference: {:+.0} ± {:.0}", elo, margin),
+        None => println!("Elo difference: not measurable while one engine scores every point"),
+    }
+    match save_error {
+        Some(error) => Err(error),
+        None => {
+            println!("Saved the games to {}", output);
+            Ok(())
+        }
+    }
+}
+
+#[cfg(feature = "bots")]
+fn simulate_command(args: &[String]) -> Result<(), String> {
+    let (_, options) = parse_options(
+        args,
+        &["variant", "games", "attacker", "defender", "time", "max-plies", "format", "output", "dataset"],
+    )?;
+    let variants = match options.get("variant") {
+        Some(name) => vec![parse_variant(Some(name))?],
+        None => Variant::ALL.to_vec(),
+    };
+    let number = |name: &str, default: usize| match options.get(name) {
+        Some(value) => value.parse().map_err(|_| format!("--{} must be a positive number", name)),
+        None => Ok(default),
+    };
+    let games = number("games", 1000)?;
+    let max_plies = number("max-plies", DEFAULT_MAX_PLIES)?;
+    let think_time_ms = number("time", 100)? as u64;
+    load_bot_data();
+    let attacker = Policy::parse(options.get("attacker").map_or("random", String::as_str))?;
+    let defender = Policy::parse(options.get("defender").map_or("random", String::as_str))?;
+    let csv = match options.get("format").map(String::as_str) {
+        None | Some("json") => false,
+        Some("csv") => true,
+        Some(other) => return Err(format!("Unknown format: {}", other)),
+    };
+
+    let mut all_stats = Vec::new();
+    let mut datasets = Vec::new();
+    for variant in variants {
+        let config = SimulationConfig { variant, games, max_plies, record_positions: options.contains_key("dataset") };
+        let start = Instant::now();
+        let summaries = simulate(&config, |side| {
+            let policy = if side == Side::Attacker { &attacker } else { &defender };
+            policy.create(side, variant, think_time_ms)
+        });
+        eprintln!("Played {} games of {} in {:.1}s", summaries.len(), variant, start.elapsed().as_secs_f64());
+        all_stats.push(SimulationStats::from_games(variant, &summaries));
+        datasets.push((variant, summaries));
+    }
+
+    let report = if csv {
+        let mut lines = vec![SimulationStats::csv_header()];
+        lines.extend(all_stats.iter().map(SimulationStats::csv_row));
+        lines.join("\n") + "\n"
+    } else {
+        serde_json::to_string_pretty(&all_stats).map_err(|error| error.to_string())? + "\n"
+    };
+    match options.get("output") {
+        Some(path) => {
+            std::fs::write(path, report).map_err(|error| format!("Cannot write {}: {}", path, error))?;
+            eprintln!("Saved the statistics to {}", path);
+        }
+        None => print!("{}", report),
+    }
+
+    if let Some(path) = options.get("dataset") {
+        let rows = write_dataset(path, &datasets)?;
+        eprintln!("Saved {} positions to {}", rows, path);
+    }
+    Ok(())
+}
@@ -0,0 +1,140 @@
+// Runs the rules fixtures in tests/fixtures against the engines. Each variant
+// has a JSON file of cases: a starting position, one move, and either the
+// expected captures and outcome or "illegal": true. perft.json pins the move
+// generation counts from the starting positions.
+
+use serde::Deserialize;
+use std::fs;
+use std::path::PathBuf;
+
+use crate::engine::{parse_square, position_string, Game, Move, Rules, Side, Variant};
+use crate::perft::perft;
+
+#[derive(Deserialize)]
+struct RulesFixture {
+    name: String,
+    position: String,
+    #[serde(rename = "move")]
+    mv: String,
+    #[serde(default)]
+    captures: Vec<String>,
+    #[serde(default)]
+    outcome: Option<Side>,
+    #[serde(default)]
+    illegal: bool,
+}
+
+#[derive(Deserialize)]
+struct PerftFixture {
+    variant: String,    // Fixtures of variants compiled out are skipped
+    depth: u32,
+    nodes: u64,
+}
+
+fn fixture_path(file: &str) -> PathBuf {
+    PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures").join(file)
+}
+
+fn load<T: for<'de> Deserialize<'de>>(file: &str) -> Vec<T> {
+    let path = fixture_path(file);
+    let text = fs::read_to_string(&path).unwrap_or_else(|error| panic!("{}: {}", path.display(), error));
+    serde_json::from_str(&text).unwrap_or_else(|error| panic!("{}: {}", path.display(), error))
+}
+
+/// Runs every case for a variant and reports all failures together.
+fn run_rules_fixtures(variant: Variant) {
+    let fixtures: Vec<RulesFixture> = load(&format!("{}.json", variant));
+    assert!(!fixtures.is_empty(), "no fixtures for {}", variant);
+    let mut failures = Vec::new();
+
+    for fixture in &fixtures {
+        if let Err(error) = run_rules_fixture(variant, fixture) {
+            failures.push(format!("{}: {}", fixture.name, error));
+        }
+    }
+
+    assert!(failures.is_empty(), "{} fixture failures:\n{}", variant, failures.join("\n"));
+}
+
+fn run_rules_fixture(variant: Variant, fixture: &RulesFixture) -> Result<(), String> {
+    let mut game = Game::from_position(variant, &fixture.position)?;
+    let size = game.size();
+    let mv = Move::parse(&fixture.mv, size)?;
+    let legal = game.legal_moves().contains(&mv);
+
+    if fixture.illegal {
+        if legal {
+            return Err(format!("{} should be illegal", fixture.mv));
+        }
+        if game.play(mv).is_ok() {
+            return Err(format!("{} was accepted", fixture.mv));
+        }
+        return Ok(());
+    }
+    if !legal {
+        return Err(format!("{} is not generated as a legal move", fixture.mv));
+    }
+
+    let mut captured = game.play(mv)?;
+    let mut expected = fixture
+        .captures
+        .iter()
+        .map(|square| parse_square(square, size))
+        .collect::<Result<Vec<_>, _>>()?;
+    captured.sort();
+    expected.sort();
+    if captured != expected {
+        return Err(format!("expected captures {:?}, got {:?}", expected, captured));
+    }
+    if game.winner() != fixture.outcome {
+        return Err(format!("expected outcome {:?}, got {:?}", fixture.outcome, game.winner()));
+    }
+
+    // Taking the move back must restore the fixture's position
+    game.undo()?;
+    if position_string(&game) != fixture.position {
+        return Err(format!("undo left {}", position_string(&game)));
+    }
+    Ok(())
+}
+
+#[test]
+#[cfg(feature = "tablut")]
+fn tablut_rules() {
+    run_rules_fixtures(Variant::Tablut);
+}
+
+#[test]
+#[cfg(feature = "brandubh")]
+fn brandubh_rules() {
+    run_rules_fixtures(Variant::Brandubh);
+}
+
+#[test]
+#[cfg(feature = "hnefatafl")]
+fn hnefatafl_rules() {
+    run_rules_fixtures(Variant::Hnefatafl);
+}
+
+#[test]
+#[cfg(feature = "koch")]
+fn koch_rules() {
+    run_rules_fixtures(Variant::Koch);
+}
+
+#[test]
+fn perft_counts() {
+    for fixture in load::<PerftFixture>("perft.json") {
+        let Some(variant) = Variant::from_name(&fixture.variant) else {
+            continue;
+        };
+        let mut game = Game::new(variant);
+        assert_eq!(
+            perft(&mut game, fixture.depth),
+            fixture.nodes,
+            "perft({}) for {}",
+            fixture.depth,
+            variant
+        );
+    }
+}
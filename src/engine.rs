@@ -0,0 +1,674 @@
+// Variant-independent view of the rules engines. Tools that work with every
+// variant (perft, bots, analysis) go through the `Rules` trait, which each
+// variant's GameState implements on top of its own move generation.
+
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+#[cfg(feature = "brandubh")]
+use crate::brandubh;
+#[cfg(feature = "hnefatafl")]
+use crate::hnefatafl;
+#[cfg(feature = "koch")]
+use crate::koch;
+#[cfg(feature = "tablut")]
+use crate::tablut;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Variant {
+    #[cfg(feature = "tablut")]
+    Tablut,
+    #[cfg(feature = "brandubh")]
+    Brandubh,
+    #[cfg(feature = "hnefatafl")]
+    Hnefatafl,
+    #[cfg(feature = "koch")]
+    Koch,
+}
+
+impl Variant {
+    /// The variants compiled into this build, each behind its cargo feature
+    pub const ALL: &'static [Variant] = &[
+        #[cfg(feature = "tablut")]
+        Variant::Tablut,
+        #[cfg(feature = "brandubh")]
+        Variant::Brandubh,
+        #[cfg(feature = "hnefatafl")]
+        Variant::Hnefatafl,
+        #[cfg(feature = "koch")]
+        Variant::Koch,
+    ];
+
+    /// Lowercase name used in URLs, forms and on the command line
+    pub fn name(&self) -> &'static str {
+        match self {
+            #[cfg(feature = "tablut")]
+            Variant::Tablut => "tablut",
+            #[cfg(feature = "brandubh")]
+            Variant::Brandubh => "brandubh",
+            #[cfg(feature = "hnefatafl")]
+            Variant::Hnefatafl => "hnefatafl",
+            #[cfg(feature = "koch")]
+            Variant::Koch => "koch",
+        }
+    }
+
+    /// Capitalised name shown on the pages
+    pub fn title(&self) -> &'static str {
+        match self {
+            #[cfg(feature = "tablut")]
+            Variant::Tablut => "Tablut",
+            #[cfg(feature = "brandubh")]
+            Variant::Brandubh => "Brandubh",
+            #[cfg(feature = "hnefatafl")]
+            Variant::Hnefatafl => "Hnefatafl",
+            #[cfg(feature = "koch")]
+            Variant::Koch => "Koch",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Variant> {
+        Variant::ALL.iter().copied().find(|variant| variant.name() == name.to_lowercase())
+    }
+}
+
+impl fmt::Display for Variant {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Side {
+    Attacker,
+    Defender,
+}
+
+impl Side {
+    pub fn opponent(&self) -> Side {
+        match self {
+            Side::Attacker => Side::Defender,
+            Side::Defender => Side::Attacker,
+        }
+    }
+
+    /// Lowercase name, matching the roles stored for online players
+    pub fn name(&self) -> &'static str {
+        match self {
+            Side::Attacker => "attacker",
+            Side::Defender => "defender",
+        }
+    }
+
+    /// Capitalised name shown in board messages
+    pub fn title(&self) -> &'static str {
+        match self {
+            Side::Attacker => "Attacker",
+            Side::Defender => "Defender",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Side> {
+        match name.to_lowercase().as_str() {
+            "attacker" => Some(Side::Attacker),
+            "defender" => Some(Side::Defender),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for Side {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum Piece {
+    Attacker,
+    Defender,
+    King,
+}
+
+impl Piece {
+    pub fn side(&self) -> Side {
+        match self {
+            Piece::Attacker => Side::Attacker,
+            Piece::Defender | Piece::King => Side::Defender,
+        }
+    }
+}
+
+/// A move of one piece in a straight line, as (row, col) board coordinates
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct Move {
+    pub from: (usize, usize),
+    pub to: (usize, usize),
+}
+
+impl Move {
+    pub fn new(from: (usize, usize), to: (usize, usize)) -> Self {
+        Move { from, to }
+    }
+
+    /// Formats the move in the board's coordinates, e.g. "d1-d3"
+    pub fn notation(&self, size: usize) -> String {
+        format!("{}-{}", square_name(self.from, size), square_name(self.to, size))
+    }
+
+    /// Parses a move written as two squares, e.g. "d1-d3" or "d1d3"
+    pub fn parse(text: &str, size: usize) -> Result<Move, String> {
+        let text = text.trim();
+        let squares: Vec<&str> = if text.contains('-') {
+            text.split('-').collect()
+        } else {
+            // Split before the second column letter
+            match text.char_indices().skip(1).find(|(_, c)| c.is_ascii_alphabetic()) {
+                Some((i, _)) => vec![&text[..i], &text[i..]],
+                None => vec![text],
+            }
+        };
+        if squares.len() != 2 {
+            return Err(format!("Invalid move notation: {}", text));
+        }
+        Ok(Move::new(parse_square(squares[0], size)?, parse_square(squares[1], size)?))
+    }
+}
+
+/// Name of a square as shown on the board: column letter, then the row
+/// number counted from the bottom edge, e.g. (6, 3) on a 7x7 board is "d1".
+pub fn square_name(pos: (usize, usize), size: usize) -> String {
+    format!("{}{}", (b'a' + pos.1 as u8) as char, size - pos.0)
+}
+
+/// Parses a square name written by `square_name`.
+pub fn parse_square(text: &str, size: usize) -> Result<(usize, usize), String> {
+    let mut chars = text.trim().chars();
+    let col = match chars.next() {
+        Some(c) if c.is_ascii_lowercase() => (c as u8 - b'a') as usize,
+        _ => return Err(format!("Invalid square: {}", text)),
+    };
+    let rank: usize = chars.as_str().parse().map_err(|_| format!("Invalid square: {}", text))?;
+    if col >= size || rank == 0 || rank > size {
+        return Err(format!("Square out of bounds: {}", text));
+    }
+    Ok((size - rank, col))
+}
+
+/// Common interface to the rules of every variant.
+pub trait Rules: Clone + Send + 'static {
+    fn size(&self) -> usize;
+    fn piece_at(&self, pos: (usize, usize)) -> Option<Piece>;
+    fn side_to_move(&self) -> Side;
+    fn winner(&self) -> Option<Side>;
+
+    /// Incrementally maintained Zobrist hash of the position
+    fn position_hash(&self) -> u64;
+
+    /// Zobrist hash recomputed from scratch, for consistency checks
+    fn recompute_hash(&self) -> u64;
+
+    /// All legal moves for the side to move (empty once the game is over)
+    fn legal_moves(&self) -> Vec<Move>;
+
+    /// Plays a legal move and returns the squares of the captured pieces.
+    fn play(&mut self, mv: Move) -> Result<Vec<(usize, usize)>, String>;
+
+    /// Takes back the last move played.
+    fn undo(&mut self) -> Result<(), String>;
+
+    /// Moves played since the start position, oldest first.
+    fn history(&self) -> Vec<Move>;
+
+    /// Replaces the pieces on the board and the side to move, clearing the history.
+    fn set_position(&mut self, pieces: &[Vec<Option<Piece>>], side: Side) -> Result<(), String>;
+}
+
+/// Pieces on each square of a board, row by row from the top
+pub type Pieces = Vec<Vec<Option<Piece>>>;
+
+/// Writes the position as a compact string: ranks from the top separated by
+/// '/', 'a' for attackers, 'd' for defenders, 'k' for the king and digits for
+/// runs of empty squares, followed by the side to move ('a' or 'd').
+/// The Brandubh start position is "3a3/3a3/3d3/aadkdaa/3d3/3a3/3a3 a".
+pub fn position_string<G: Rules>(game: &G) -> String {
+    let size = game.size();
+    let mut ranks = Vec::with_capacity(size);
+    for row in 0..size {
+        let mut rank = String::new();
+        let mut empty = 0;
+        for col in 0..size {
+            let symbol = match game.piece_at((row, col)) {
+                Some(Piece::Attacker) => 'a',
+                Some(Piece::Defender) => 'd',
+                Some(Piece::King) => 'k',
+                None => {
+                    empty += 1;
+                    continue;
+                }
+            };
+            if empty > 0 {
+                rank.push_str(&empty.to_string());
+                empty = 0;
+            }
+            rank.push(symbol);
+        }
+        if empty > 0 {
+            rank.push_str(&empty.to_string());
+        }
+        ranks.push(rank);
+    }
+    let side = match game.side_to_move() {
+        Side::Attacker => 'a',
+        Side::Defender => 'd',
+    };
+    format!("{} {}", ranks.join("/"), side)
+}
+
+/// Parses a string written by `position_string` into pieces and side to move.
+pub fn parse_position(text: &str, size: usize) -> Result<(Pieces, Side), String> {
+    let mut parts = text.split_whitespace();
+    let board = parts.next().ok_or("Empty position string.")?;
+    let side = match parts.next() {
+        Some("a") | None => Side::Attacker,
+        Some("d") => Side::Defender,
+        Some(other) => return Err(format!("Invalid side to move: {}", other)),
+    };
+
+    let ranks: Vec<&str> = board.split('/').collect();
+    if ranks.len() != size {
+        return Err(format!("Expected {} ranks, found {}.", size, ranks.len()));
+    }
+
+    // Adds a run of empty squares, refusing runs that overflow the rank
+    // before any memory is taken for them
+    let add_empty = |row: &mut Vec<Option<Piece>>, digits: &str, rank: &str| -> Result<(), String> {
+        let empty: usize = digits.parse().map_err(|_| format!("Invalid rank: {}", rank))?;
+        if empty > size - row.len() {
+            return Err(format!("Rank has more than {} squares: {}", size, rank));
+        }
+        row.extend(std::iter::repeat_n(None, empty));
+        Ok(())
+    };
+
+    let mut pieces = Vec::with_capacity(size);
+    for rank in ranks {
+        let mut row = Vec::with_capacity(size);
+        let mut digits = String::new();
+        for c in rank.chars() {
+            if c.is_ascii_digit() {
+                digits.push(c);
+                continue;
+            }
+            if !digits.is_empty() {
+                add_empty(&mut row, &digits, rank)?;
+                digits.clear();
+            }
+            if row.len() == size {
+                return Err(format!("Rank has more than {} squares: {}", size, rank));
+            }
+            row.push(Some(match c {
+                'a' => Piece::Attacker,
+                'd' => Piece::Defender,
+                'k' => Piece::King,
+                _ => return Err(format!("Invalid piece '{}' in rank: {}", c, rank)),
+            }));
+        }
+        if !digits.is_empty() {
+            add_empty(&mut row, &digits, rank)?;
+        }
+        if row.len() != size {
+            return Err(format!("Rank has {} squares instead of {}: {}", row.len(), size, rank));
+        }
+        pieces.push(row);
+    }
+
+    if pieces.iter().flatten().filter(|piece| **piece == Some(Piece::King)).count() != 1 {
+        return Err("Position must contain exactly one king.".to_string());
+    }
+
+    Ok((pieces, side))
+}
+
+// The variant modules share the same GameState layout, so one implementation
+// of `Rules` covers all of them.
+macro_rules! impl_rules {
+    ($module:ident, $variant:expr) => {
+        impl Rules for $module::GameState {
+            fn size(&self) -> usize {
+                self.board.len()
+            }
+
+            fn piece_at(&self, pos: (usize, usize)) -> Option<Piece> {
+                match self.board[pos.0][pos.1].cell_type {
+                    $module::CellType::Empty => None,
+                    $module::CellType::Attacker => Some(Piece::Attacker),
+                    $module::CellType::Defender => Some(Piece::Defender),
+                    $module::CellType::King => Some(Piece::King),
+                }
+            }
+
+            fn side_to_move(&self) -> Side {
+                match self.current_turn.cell_type {
+                    $module::CellType::Defender => Side::Defender,
+                    _ => Side::Attacker,
+                }
+            }
+
+            fn winner(&self) -> Option<Side> {
+                self.winner.map(|cell| match cell.cell_type {
+                    $module::CellType::Attacker => Side::Attacker,
+                    _ => Side::Defender,
+                })
+            }
+
+            fn position_hash(&self) -> u64 {
+                self.hash
+            }
+
+            fn recompute_hash(&self) -> u64 {
+                self.compute_hash()
+            }
+
+            fn legal_moves(&self) -> Vec<Move> {
+                let mut moves = Vec::new();
+                if self.game_over {
+                    return moves;
+                }
+                let side = self.side_to_move();
+                for row in 0..self.board.len() {
+                    for col in 0..self.board.len() {
+                        if self.piece_at((row, col)).map(|piece| piece.side()) == Some(side) {
+                            for to in self.calculate_valid_moves((row, col)) {
+                                moves.push(Move::new((row, col), to));
+                            }
+                        }
+                    }
+                }
+                moves
+            }
+
+            fn play(&mut self, mv: Move) -> Result<Vec<(usize, usize)>, String> {
+                if self.game_over {
+                    return Err("Game is already over.".to_string());
+                }
+                let size = self.board.len();
+                if mv.from.0 >= size || mv.from.1 >= size || mv.to.0 >= size || mv.to.1 >= size {
+                    return Err("Invalid cell coordinates.".to_string());
+                }
+                if self.piece_at(mv.from).map(|piece| piece.side()) != Some(self.side_to_move()) {
+                    return Err(format!("No {} piece on {}.", self.side_to_move(), square_name(mv.from, size)));
+                }
+                if !self.calculate_valid_moves(mv.from).contains(&mv.to) {
+                    return Err(format!("Illegal move: {}", mv.notation(size)));
+                }
+                self.make_move(mv.from, mv.to)?;
+                Ok(self
+                    .undo_stack
+                    .last()
+                    .map(|record| record.captured.iter().map(|&(pos, _)| pos).collect())
+                    .unwrap_or_default())
+            }
+
+            fn undo(&mut self) -> Result<(), String> {
+                self.unmake()
+            }
+
+            fn history(&self) -> Vec<Move> {
+                self.undo_stack.iter().map(|record| Move::new(record.from, record.to)).collect()
+            }
+
+            fn set_position(&mut self, pieces: &[Vec<Option<Piece>>], side: Side) -> Result<(), String> {
+                if pieces.len() != self.board.len() || pieces.iter().any(|row| row.len() != self.board.len()) {
+                    return Err(format!("{} is played on a {}x{} board.", $variant, self.board.len(), self.board.len()));
+                }
+                for (row, cells) in self.board.iter_mut().zip(pieces) {
+                    for (cell, piece) in row.iter_mut().zip(cells) {
+                        cell.cell_type = match piece {
+                            None => $module::CellType::Empty,
+                            Some(Piece::Attacker) => $module::CellType::Attacker,
+                            Some(Piece::Defender) => $module::CellType::Defender,
+                            Some(Piece::King) => $module::CellType::King,
+                        };
+                    }
+                }
+                self.current_turn.cell_type = match side {
+                    Side::Attacker => $module::CellType::Attacker,
+                    Side::Defender => $module::CellType::Defender,
+                };
+                self.game_over = false;
+                self.winner = None;
+                self.move_done = false;
+                self.undo_stack.clear();
+                self.board_message = format!("Current turn: {}", self.current_turn.cell_type);
+                self.hash = self.compute_hash();
+                Ok(())
+            }
+        }
+    };
+}
+
+#[cfg(feature = "tablut")]
+impl_rules!(tablut, Variant::Tablut);
+#[cfg(feature = "brandubh")]
+impl_rules!(brandubh, Variant::Brandubh);
+#[cfg(feature = "hnefatafl")]
+impl_rules!(hnefatafl, Variant::Hnefatafl);
+#[cfg(feature = "koch")]
+impl_rules!(koch, Variant::Koch);
+
+/// A game of any variant, for code that picks the variant at run time.
+#[derive(Debug, Clone)]
+pub enum Game {
+    #[cfg(feature = "tablut")]
+    Tablut(tablut::GameState),
+    #[cfg(feature = "brandubh")]
+    Brandubh(brandubh::GameState),
+    #[cfg(feature = "hnefatafl")]
+    Hnefatafl(hnefatafl::GameState),
+    #[cfg(feature = "koch")]
+    Koch(koch::GameState),
+}
+
+macro_rules! dispatch {
+    ($game:expr, $state:ident => $body:expr) => {
+        match $game {
+            #[cfg(feature = "tablut")]
+            Game::Tablut($state) => $body,
+            #[cfg(feature = "brandubh")]
+            Game::Brandubh($state) => $body,
+            #[cfg(feature = "hnefatafl")]
+            Game::Hnefatafl($state) => $body,
+            #[cfg(feature = "koch")]
+            Game::Koch($state) => $body,
+        }
+    };
+}
+
+impl Game {
+    /// Starts a game of the given variant from its initial position.
+    pub fn new(variant: Variant) -> Self {
+        match variant {
+            #[cfg(feature = "tablut")]
+            Variant::Tablut => Game::Tablut(tablut::GameState::new(0)),
+            #[cfg(feature = "brandubh")]
+            Variant::Brandubh => Game::Brandubh(brandubh::GameState::new(0)),
+            #[cfg(feature = "hnefatafl")]
+            Variant::Hnefatafl => Game::Hnefatafl(hnefatafl::GameState::new(0)),
+            #[cfg(feature = "koch")]
+            Variant::Koch => Game::Koch(koch::GameState::new(0)),
+        }
+    }
+
+    pub fn variant(&self) -> Variant {
+        match self {
+            #[cfg(feature = "tablut")]
+            Game::Tablut(_) => Variant::Tablut,
+            #[cfg(feature = "brandubh")]
+            Game::Brandubh(_) => Variant::Brandubh,
+            #[cfg(feature = "hnefatafl")]
+            Game::Hnefatafl(_) => Variant::Hnefatafl,
+            #[cfg(feature = "koch")]
+            Game::Koch(_) => Variant::Koch,
+        }
+    }
+
+    /// Sets up a game of the given variant from a position string.
+    pub fn from_position(variant: Variant, position: &str) -> Result<Self, String> {
+        let mut game = Game::new(variant);
+        let (pieces, side) = parse_position(position, game.size())?;
+        game.set_position(&pieces, side)?;
+        Ok(game)
+    }
+}
+
+impl Rules for Game {
+    fn size(&self) -> usize {
+        dispatch!(self, state => state.size())
+    }
+
+    fn piece_at(&self, pos: (usize, usize)) -> Option<Piece> {
+        dispatch!(self, state => state.piece_at(pos))
+    }
+
+    fn side_to_move(&self) -> Side {
+        dispatch!(self, state => state.side_to_move())
+    }
+
+    fn winner(&self) -> Option<Side> {
+        dispatch!(self, state => Rules::winner(state))
+    }
+
+    fn position_hash(&self) -> u64 {
+        dispatch!(self, state => state.position_hash())
+    }
+
+    fn recompute_hash(&self) -> u64 {
+        dispatch!(self, state => state.recompute_hash())
+    }
+
+    fn legal_moves(&self) -> Vec<Move> {
+        dispatch!(self, state => state.legal_moves())
+    }
+
+    fn play(&mut self, mv: Move) -> Result<Vec<(usize, usize)>, String> {
+        dispatch!(self, state => state.play(mv))
+    }
+
+    fn undo(&mut self) -> Result<(), String> {
+        dispatch!(self, state => state.undo())
+    }
+
+    fn history(&self) -> Vec<Move> {
+        dispatch!(self, state => state.history())
+    }
+
+    fn set_position(&mut self, pieces: &[Vec<Option<Piece>>], side: Side) -> Result<(), String> {
+        dispatch!(self, state => state.set_position(pieces, side))
+    }
+}
+
+/// Test for a variant module: makes moves directly on the variant's game
+/// state and takes them back with `unmake`, checking that every cell, the
+/// hash, the history and the status come back: for capture and king-capture
+/// positions given as (position, move, captures, outcome), then for a seeded
+/// random game from the start.
+#[cfg(test)]
+macro_rules! unmake_tests {
+    ($name:ident, $variant:ident, [$(($position:literal, $mv:literal, $captures:literal, $outcome:expr)),* $(,)?]) => {
+        #[test]
+        fn $name() {
+            use $crate::engine::{Game, Move, Rules, Variant};
+            use rand::rngs::StdRng;
+            use rand::seq::SliceRandom;
+            use rand::SeedableRng;
+
+            #[allow(unreachable_patterns)]
+            let state_of = |game: Game| match game {
+                Game::$variant(state) => state,
+                _ => unreachable!(),
+            };
+            let snapshot = |state: &Game| {
+                #[allow(unreachable_patterns)]
+                match state {
+                    Game::$variant(state) => (
+                        state.board.clone(),
+                        state.hash,
+                        state.history(),
+                        state.current_turn,
+                        state.game_over,
+                        state.winner,
+                    ),
+                    _ => unreachable!(),
+                }
+            };
+
+            $(
+                let game = Game::from_position(Variant::$variant, $position).unwrap();
+                let before = snapshot(&game);
+                let mut state = state_of(game);
+                let mv = Move::parse($mv, state.size()).unwrap();
+                state.make_move(mv.from, mv.to).unwrap();
+                assert_eq!(state.undo_stack.last().unwrap().captured.len(), $captures, "captures of {}", $mv);
+                assert_eq!(Rules::winner(&state), $outcome, "outcome of {}", $mv);
+                assert_ne!(state.hash, before.1);
+
+                state.unmake().unwrap();
+                assert!(snapshot(&Game::$variant(state)) == before, "unmake of {} in {}", $mv, $position);
+            )*
+
+            // A random game played forward, then taken back move by move
+            let mut rng = StdRng::seed_from_u64(26);
+            let mut game = Game::new(Variant::$variant);
+            let mut snapshots = Vec::new();
+            while snapshots.len() < 60 && game.winner().is_none() {
+                let Some(&mv) = game.legal_moves().choose(&mut rng) else { break };
+                snapshots.push(snapshot(&game));
+                let mut state = state_of(game);
+                state.make_move(mv.from, mv.to).unwrap();
+                game = Game::$variant(state);
+            }
+            while let Some(before) = snapshots.pop() {
+                let mut state = state_of(game);
+                state.unmake().unwrap();
+                game = Game::$variant(state);
+                assert!(snapshot(&game) == before, "unmake at ply {}", snapshots.len());
+            }
+            assert!(state_of(game).unmake().is_err(), "unmake with no move made");
+        }
+    };
+}
+#[cfg(test)]
+pub(crate) use unmake_tests;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn malformed_positions_are_rejected() {
+        for text in [
+            "3a3/3a3/3d3/aadkdaa/3d3/3a3/3a3 a",
+            "7/7/7/3k3/7/7/7 d",
+        ] {
+            let (pieces, _) = parse_position(text, 7).unwrap();
+            assert!(pieces.iter().all(|row| row.len() == 7));
+        }
+        for text in [
+            "3a3/3a3/3d3/aadkdaa/3d3/3a3/3a4 a",
+            "3a3/3a3/3d3/aadkdaa/3d3/3a3/3a3a a",
+            "3a3/3a3/3d3/aadkdaa/3d3/3a3/99999999999999999 a",
+            "3a3/3a3/3d3/aadkdaa/3d3/3a3/18446744073709551615 a",
+            "3a3/3a3/3d3/aadkdaa/3d3/3a3/999999999999999999999999 a",
+            "3a3/3a3/3d3/aadkdaa/3d3/3a3/2a3 a",
+            "3a3/3a3/3d3/aadkdaa/3d3/3a3 a",
+            "3a3/3a3/3d3/aadadaa/3d3/3a3/3a3 a",
+            "3a3/3a3/3d3/aadkdaa/3d3/3a3/3x3 a",
+            "3a3/3a3/3d3/aadkdaa/3d3/3a3/3a3 x",
+        ] {
+            assert!(parse_position(text, 7).is_err(), "{}", text);
+        }
+    }
+}
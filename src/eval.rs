@@ -0,0 +1,269 @@
+// Static evaluation of tafl positions for the search bots.
+
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs;
+use std::sync::OnceLock;
+
+use crate::engine::{Piece, Rules, Side, Variant};
+
+/// Score of a won position, reduced by the distance to the win so that
+/// faster wins are preferred.
+pub const WIN_SCORE: i32 = 100_000;
+
+/// Weights file written by the tuning tool and loaded at startup
+pub const WEIGHTS_FILE: &str = "weights.json";
+
+/// Weights of the evaluation terms. Positive scores favour the attackers.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Weights {
+    pub attacker_value: i32,        // Value of each attacker on the board
+    pub defender_value: i32,        // Value of each defender on the board (king excluded)
+    pub king_corner_distance: i32,  // Per square between the king and its nearest corner
+    pub king_escape_routes: i32,    // Per corner the king can reach in one move
+    pub king_open_lines: i32,       // Per king move that reaches an edge square
+    pub attacker_ring: i32,         // Per attacker on the eight squares around the king
+}
+
+impl Default for Weights {
+    fn default() -> Self {
+        Weights {
+            attacker_value: 100,
+            defender_value: 180,
+            king_corner_distance: 12,
+            king_escape_routes: 600,
+            king_open_lines: 25,
+            attacker_ring: 30,
+        }
+    }
+}
+
+/// Tuned weights per variant, loaded from the weights file
+static TUNED_WEIGHTS: OnceLock<HashMap<Variant, Weights>> = OnceLock::new();
+
+impl Weights {
+    /// Tuned weights of the variant if the weights file provided them, the
+    /// defaults otherwise.
+    pub fn for_variant(variant: Variant) -> Weights {
+        TUNED_WEIGHTS
+            .get()
+            .and_then(|weights| weights.get(&variant))
+            .cloned()
+            .unwrap_or_default()
+    }
+
+    /// The weights in the order of the `Features` counts
+    pub fn values(&self) -> [i32; FEATURE_COUNT] {
+        [
+            self.attacker_value,
+            self.defender_value,
+            self.king_corner_distance,
+            self.king_escape_routes,
+            self.king_open_lines,
+            self.attacker_ring,
+        ]
+    }
+
+    pub fn from_values(values: [i32; FEATURE_COUNT]) -> Weights {
+        let [attacker_value, defender_value, king_corner_distance, king_escape_routes, king_open_lines, attacker_ring] = values;
+        Weights {
+            attacker_value,
+            defender_value,
+            king_corner_distance,
+            king_escape_routes,
+            king_open_lines,
+            attacker_ring,
+        }
+    }
+
+    /// Scores a position's features from the attackers' point of view.
+    pub fn score(&self, features: &Features) -> i32 {
+        self.values().iter().zip(features.0).map(|(weight, count)| weight * count).sum()
+    }
+}
+
+/// Reads the per-variant weights file, keyed by variant name.
+pub fn read_weights_file(path: &str) -> Result<HashMap<Variant, Weights>, String> {
+    let text = fs::read_to_string(path).map_err(|error| format!("Cannot read {}: {}", path, error))?;
+    let entries: HashMap<String, Weights> =
+        serde_json::from_str(&text).map_err(|error| format!("Invalid weights file {}: {}", path, error))?;
+    entries
+        .into_iter()
+        .map(|(name, weights)| match Variant::from_name(&name) {
+            Some(variant) => Ok((variant, weights)),
+            None => Err(format!("Unknown variant in {}: {}", path, name)),
+        })
+        .collect()
+}
+
+/// Writes the per-variant weights file, keyed by variant name.
+pub fn write_weights_file(path: &str, weights: &HashMap<Variant, Weights>) -> Result<(), String> {
+    let entries: std::collections::BTreeMap<&str, &Weights> =
+        weights.iter().map(|(variant, weights)| (variant.name(), weights)).collect();
+    let text = serde_json::to_string_pretty(&entries).map_err(|error| error.to_string())?;
+    fs::write(path, text + "\n").map_err(|error| format!("Cannot write {}: {}", path, error))
+}
+
+/// Loads the tuned weights used by `Weights::for_variant`, if the file
+/// exists, and returns the number of variants it covers.
+pub fn load_tuned_weights(path: &str) -> Result<usize, String> {
+    if !std::path::Path::new(path).exists() {
+        return Ok(0);
+    }
+    let weights = read_weights_file(path)?;
+    let count = weights.len();
+    TUNED_WEIGHTS.set(weights).map_err(|_| "Tuned weights are already loaded".to_string())?;
+    Ok(count)
+}
+
+/// Number of evaluation terms
+pub const FEATURE_COUNT: usize = 6;
+
+/// Counts of each evaluation term in a position, signed so that the score is
+/// the sum of each count times its weight.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Features(pub [i32; FEATURE_COUNT]);
+
+/// Measures the evaluation terms of a position that is not won yet.
+pub fn features<G: Rules>(game: &G) -> Features {
+    let size = game.size();
+    let mut attackers = 0;
+    let mut defenders = 0;
+    let mut king = None;
+
+    for row in 0..size {
+        for col in 0..size {
+            match game.piece_at((row, col)) {
+                Some(Piece::Attacker) => attackers += 1,
+                Some(Piece::Defender) => defenders += 1,
+                Some(Piece::King) => king = Some((row, col)),
+                None => {}
+            }
+        }
+    }
+
+    let (kr, kc) = match king {
+        Some(pos) => pos,
+        None => return Features([attackers, -defenders, 0, 0, 0, 0]),
+    };
+
+    // Distance to the nearest corner: the further the king, the better for the attackers
+    let last = size - 1;
+    let corner_distance = [(0, 0), (0, last), (last, 0), (last, last)]
+        .iter()
+        .map(|&(r, c)| kr.abs_diff(r) + kc.abs_diff(c))
+        .min()
+        .unwrap_or(0);
+
+    // Open lines from the king to the edge, and corners reachable in one move
+    let (escape_routes, open_lines) = king_lines(game, (kr, kc));
+
+    // Attackers closing in around the king
+    let mut ring = 0;
+    for dr in -1isize..=1 {
+        for dc in -1isize..=1 {
+            if dr == 0 && dc == 0 {
+                continue;
+            }
+            let (r, c) = (kr as isize + dr, kc as isize + dc);
+            if r >= 0 && c >= 0 && (r as usize) < size && (c as usize) < size
+                && game.piece_at((r as usize, c as usize)) == Some(Piece::Attacker)
+            {
+                ring += 1;
+            }
+        }
+    }
+
+    Features([attackers, -defenders, corner_distance as i32, -escape_routes, -open_lines, ring])
+}
+
+/// Scores a position from the attackers' point of view.
+pub fn evaluate_for_attacker<G: Rules>(game: &G, weights: &Weights) -> i32 {
+    match game.winner() {
+        Some(Side::Attacker) => WIN_SCORE,
+        Some(Side::Defender) => -WIN_SCORE,
+        None => weights.score(&features(game)),
+    }
+}
+
+/// Counts the corners the king can reach in one move and the directions in
+/// which its path to the board edge is clear.
+fn king_lines<G: Rules>(game: &G, king: (usize, usize)) -> (i32, i32) {
+    let size = game.size() as isize;
+    let mut corners = 0;
+    let mut open = 0;
+
+    for (dr, dc) in [(-1, 0), (1, 0), (0, -1), (0, 1)] {
+        let (mut r, mut c) = (king.0 as isize, king.1 as isize);
+        let mut clear = true;
+        loop {
+            r += dr;
+            c += dc;
+            if r < 0 || c < 0 || r >= size || c >= size {
+                break;
+            }
+            if game.piece_at((r as usize, c as usize)).is_some() {
+                clear = false;
+                break;
+            }
+        }
+        if clear && (r - dr, c - dc) != (king.0 as isize, king.1 as isize) {
+            open += 1;
+            let (er, ec) = (r - dr, c - dc);
+            if (er == 0 || er == size - 1) && (ec == 0 || ec == size - 1) {
+                corners += 1;
+            }
+        }
+    }
+
+    (corners, open)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_path(name: &str) -> String {
+        let path = std::env::temp_dir().join(format!("{}-{}.json", name, std::process::id()));
+        path.to_str().unwrap().to_string()
+    }
+
+    #[test]
+    fn weights_files_read_back_per_variant() {
+        let weights: HashMap<Variant, Weights> = Variant::ALL
+            .iter()
+            .enumerate()
+            .map(|(index, &variant)| {
+                let mut values = Weights::default().values();
+                values[index % FEATURE_COUNT] += 7 * (index as i32 + 1);
+                (variant, Weights::from_values(values))
+            })
+            .collect();
+        let path = temp_path("weights-test");
+        write_weights_file(&path, &weights).unwrap();
+        let read = read_weights_file(&path);
+        fs::remove_file(&path).unwrap();
+
+        let read = read.unwrap();
+        assert_eq!(read.len(), Variant::ALL.len());
+        for (variant, weights) in &weights {
+            assert_eq!(read[variant].values(), weights.values(), "{}", variant.name());
+        }
+    }
+
+    #[test]
+    fn malformed_weights_files_are_refused() {
+        let path = temp_path("weights-invalid-test");
+        fs::write(&path, r#"{"chess": {"attacker_value": 1}}"#).unwrap();
+        let invalid = read_weights_file(&path);
+        let weights = serde_json::to_string(&Weights::default()).unwrap();
+        fs::write(&path, format!(r#"{{"chess": {}}}"#, weights)).unwrap();
+        let unknown = read_weights_file(&path);
+        fs::remove_file(&path).unwrap();
+
+        assert!(invalid.unwrap_err().starts_with("Invalid weights file"));
+        assert!(unknown.unwrap_err().starts_with("Unknown variant"));
+        assert!(read_weights_file(&path).unwrap_err().starts_with("Cannot read"));
+        assert_eq!(load_tuned_weights(&path), Ok(0));
+    }
+}
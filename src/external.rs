@@ -0,0 +1,291 @@
+// External engines run as local subprocesses and driven over stdin/stdout
+// with the OpenTafl engine protocol. Engines are declared in the engines file
+// and started on demand; idle processes are kept for the next move.
+//
+// The subset of the protocol used here, one message per line:
+//   server: hello                    engine replies: hello
+//   server: rules <rules string>     sent again whenever the variant changes
+//   server: position <position>      OpenTafl position record, e.g. /3t3/3t3/3T3/ttTKTtt/3T3/3t3/3t3/
+//   server: side <attackers|defenders>
+//   server: clock <attacker ms> <defender ms> 0 0
+//   server: play <attackers|defenders>   engine replies: move <move>, e.g. move d1-d3
+//   server: goodbye                  before the process is stopped
+// The engine may also send "status <text>" lines, which are logged, and
+// "error <text>", which abandons the request.
+
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs;
+use std::io::{BufRead, BufReader, Write};
+use std::process::{Child, ChildStdin, Command, Stdio};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError};
+use std::sync::{Mutex, OnceLock};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::engine::{Game, Move, Piece, Rules, Side, Variant};
+
+/// Engines file listing the external engines by name, loaded at startup
+pub const ENGINES_FILE: &str = "engines.json";
+
+/// Time an engine has to answer the opening hello
+const STARTUP_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Time an engine may take beyond its thinking time before it is stopped
+const MOVE_GRACE: Duration = Duration::from_secs(2);
+
+/// Time given to an engine to exit after goodbye
+const SHUTDOWN_TIMEOUT: Duration = Duration::from_millis(500);
+
+/// Idle processes kept per engine
+const MAX_IDLE_PER_ENGINE: usize = 2;
+
+/// How to start an external engine
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct EngineSpec {
+    pub command: String,
+    #[serde(default)]
+    pub args: Vec<String>,
+}
+
+/// External engines by name, from the engines file
+static ENGINES: OnceLock<BTreeMap<String, EngineSpec>> = OnceLock::new();
+
+/// Running engines waiting for their next request
+static IDLE_ENGINES: Mutex<Vec<ExternalEngine>> = Mutex::new(Vec::new());
+
+/// Loads the external engines, if the file exists, and returns how many it
+/// declares.
+pub fn load_engines(path: &str) -> Result<usize, String> {
+    if !std::path::Path::new(path).exists() {
+        return Ok(0);
+    }
+    let text = fs::read_to_string(path).map_err(|error| format!("Cannot read {}: {}", path, error))?;
+    let engines: BTreeMap<String, EngineSpec> =
+        serde_json::from_str(&text).map_err(|error| format!("Invalid engines file {}: {}", path, error))?;
+    let count = engines.len();
+    ENGINES.set(engines).map_err(|_| "External engines are already loaded".to_string())?;
+    Ok(count)
+}
+
+/// Names of the configured external engines
+pub fn engine_names() -> Vec<String> {
+    ENGINES.get().map_or_else(Vec::new, |engines| engines.keys().cloned().collect())
+}
+
+fn side_name(side: Side) -> &'static str {
+    match side {
+        Side::Attacker => "attackers",
+        Side::Defender => "defenders",
+    }
+}
+
+/// OpenTafl position record: ranks from the top, each followed by '/', with
+/// 't' for attackers, 'T' for defenders, 'K' for the king and digits for
+/// runs of empty squares.
+pub fn opentafl_position<G: Rules>(game: &G) -> String {
+    let size = game.size();
+    let mut record = String::from("/");
+    for row in 0..size {
+        let mut empty = 0;
+        for col in 0..size {
+            let symbol = match game.piece_at((row, col)) {
+                Some(Piece::Attacker) => 't',
+                Some(Piece::Defender) => 'T',
+                Some(Piece::King) => 'K',
+                None => {
+                    empty += 1;
+                    continue;
+                }
+            };
+            if empty > 0 {
+                record.push_str(&empty.to_string());
+                empty = 0;
+            }
+            record.push(symbol);
+        }
+        if empty > 0 {
+            record.push_str(&empty.to_string());
+        }
+        record.push('/');
+    }
+    record
+}
+
+/// OpenTafl rules string of a variant: board size, attackers moving first,
+/// corner escape, the king's capture strength and the start position.
+pub fn opentafl_rules(variant: Variant) -> String {
+    let (name, king_strength) = match variant {
+        #[cfg(feature = "tablut")]
+        Variant::Tablut => ("Tablut", "s"),
+        #[cfg(feature = "brandubh")]
+        Variant::Brandubh => ("Brandubh", "w"),
+        #[cfg(feature = "hnefatafl")]
+        Variant::Hnefatafl => ("Hnefatafl", "s"),
+        #[cfg(feature = "koch")]
+        Variant::Koch => ("Koch", "c"),
+    };
+    let game = Game::new(variant);
+    format!(
+        "dim:{} name:{} atkf:y esc:c ks:{} start:{}",
+        game.size(),
+        name,
+        king_strength,
+        opentafl_position(&game)
+    )
+}
+
+/// Reads a move sent by an engine, ignoring a leading piece letter and the
+/// captures OpenTafl notation may append, e.g. "Kd4-d1xc1".
+fn parse_engine_move(text: &str, size: usize) -> Result<Move, String> {
+    let text = text.trim().trim_start_matches(|c: char| c.is_ascii_uppercase());
+    let text = text.split('x').next().unwrap_or(text);
+    Move::parse(text, size)
+}
+
+/// A running engine process
+pub struct ExternalEngine {
+    name: String,
+    child: Child,
+    stdin: ChildStdin,
+    lines: Receiver<String>,
+    variant: Option<Variant>,   // Variant of the last rules sent
+    messages: Vec<String>,      // Status lines received since the last `take_messages`
+}
+
+impl ExternalEngine {
+    /// Takes an idle process of the engine, or starts a new one.
+    pub fn acquire(name: &str) -> Result<ExternalEngine, String> {
+        let mut idle = IDLE_ENGINES.lock().unwrap();
+        if let Some(index) = idle.iter().position(|engine| engine.name == name) {
+            return Ok(idle.swap_remove(index));
+        }
+        drop(idle);
+        ExternalEngine::start(name)
+    }
+
+    /// Puts the process back in the idle pool for the next request.
+    pub fn release(self) {
+        let mut idle = IDLE_ENGINES.lock().unwrap();
+        if idle.iter().filter(|engine| engine.name == self.name).count() < MAX_IDLE_PER_ENGINE {
+            idle.push(self);
+        }
+    }
+
+    fn start(name: &str) -> Result<ExternalEngine, String> {
+        let spec = ENGINES
+            .get()
+            .and_then(|engines| engines.get(name))
+            .ok_or_else(|| format!("Unknown external engine: {}", name))?;
+        let mut child = Command::new(&spec.command)
+            .args(&spec.args)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::null())
+            .spawn()
+            .map_err(|error| format!("Cannot start {}: {}", spec.command, error))?;
+        let stdin = child.stdin.take().ok_or("No stdin for the engine")?;
+        let stdout = child.stdout.take().ok_or("No stdout for the engine")?;
+
+        // Lines are read on their own thread so that waiting can time out
+        let (sender, lines) = mpsc::channel();
+        thread::spawn(move || {
+            for line in BufReader::new(stdout).lines() {
+                let Ok(line) = line else { break };
+                if sender.send(line).is_err() {
+                    break;
+                }
+            }
+        });
+
+        let mut engine = ExternalEngine {
+            name: name.to_string(),
+            child,
+            stdin,
+            lines,
+            variant: None,
+            messages: Vec::new(),
+        };
+        engine.send("hello")?;
+        let reply = engine.receive(Instant::now() + STARTUP_TIMEOUT)?;
+        if reply.trim() != "hello" {
+            return Err(format!("{} answered hello with: {}", name, reply));
+        }
+        Ok(engine)
+    }
+
+    fn send(&mut self, message: &str) -> Result<(), String> {
+        writeln!(self.stdin, "{}", message)
+            .and_then(|_| self.stdin.flush())
+            .map_err(|error| format!("{} stopped reading: {}", self.name, error))
+    }
+
+    /// Status lines the engine sent since the last call, e.g. its search
+    /// progress, for the caller to log.
+    pub fn take_messages(&mut self) -> Vec<String> {
+        std::mem::take(&mut self.messages)
+    }
+
+    /// Waits for the next protocol message, keeping status lines.
+    fn receive(&mut self, deadline: Instant) -> Result<String, String> {
+        loop {
+            let timeout = deadline.saturating_duration_since(Instant::now());
+            match self.lines.recv_timeout(timeout) {
+                Ok(line) => {
+                    if let Some(status) = line.strip_prefix("status") {
+                        self.messages.push(format!("{}: {}", self.name, status.trim()));
+                    } else if let Some(error) = line.strip_prefix("error") {
+                        return Err(format!("{} reported an error: {}", self.name, error.trim()));
+                    } else if !line.trim().is_empty() {
+                        return Ok(line);
+                    }
+                }
+                Err(RecvTimeoutError::Timeout) => return Err(format!("{} did not answer in time", self.name)),
+                Err(RecvTimeoutError::Disconnected) => return Err(format!("{} exited", self.name)),
+            }
+        }
+    }
+
+    /// Asks the engine for a move in the position and checks that it is legal.
+    pub fn request_move(&mut self, game: &Game, time_limit: Duration) -> Result<Move, String> {
+        let variant = game.variant();
+        if self.variant != Some(variant) {
+            self.send(&format!("rules {}", opentafl_rules(variant)))?;
+            self.variant = Some(variant);
+        }
+        let side = side_name(game.side_to_move());
+        let ms = time_limit.as_millis();
+        self.send(&format!("position {}", opentafl_position(game)))?;
+        self.send(&format!("side {}", side))?;
+        self.send(&format!("clock {} {} 0 0", ms, ms))?;
+        self.send(&format!("play {}", side))?;
+
+        let deadline = Instant::now() + time_limit + MOVE_GRACE;
+        loop {
+            let line = self.receive(deadline)?;
+            let Some(text) = line.strip_prefix("move ") else {
+                continue; // Messages this server does not use
+            };
+            let mv = parse_engine_move(text, game.size())?;
+            if !game.legal_moves().contains(&mv) {
+                return Err(format!("{} played an illegal move: {}", self.name, text.trim()));
+            }
+            return Ok(mv);
+        }
+    }
+}
+
+impl Drop for ExternalEngine {
+    fn drop(&mut self) {
+        let _ = self.send("goodbye");
+        let deadline = Instant::now() + SHUTDOWN_TIMEOUT;
+        while Instant::now() < deadline {
+            if let Ok(Some(_)) = self.child.try_wait() {
+                return;
+            }
+            thread::sleep(Duration::from_millis(20));
+        }
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
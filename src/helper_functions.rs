@@ -1,29 +1,41 @@
 //mod crate::tablut;
+#[cfg(feature = "tablut")]
 use crate::tablut::{Cell as TablutCell, CellType as TablutCellType};
 
 //mod crate::hnefatafl;
+#[cfg(feature = "hnefatafl")]
 use crate::hnefatafl::{Cell as HnefataflCell, CellType as HnefataflCellType};
 
 //mod crate::brandubh;
+#[cfg(feature = "brandubh")]
 use crate::brandubh::{Cell as BrandubhCell, CellType as BrandubhCellType};
 
 //mod crate::koch;
+#[cfg(feature = "koch")]
 use crate::koch::{Cell as KochCell, CellType as KochCellType};
 
+#[cfg(feature = "bots")]
+use crate::analysis::{Analysis, Judgement};
+use crate::engine::Game;
+#[cfg(feature = "bots")]
+use crate::engine::Side;
+#[cfg(feature = "bots")]
+use crate::eval::WIN_SCORE;
+
 use rand::Rng;
 use std::fs;
 
 
 // Helper function to render the board as an HTML table
-pub fn render_tablut_board_as_html(board: &Vec<Vec<TablutCell>>) -> String {
+#[cfg(feature = "tablut")]
+pub fn render_tablut_board_as_html(board: &[Vec<TablutCell>], selected: Option<(usize, usize)>, targets: &[(usize, usize)]) -> String {
     let mut html = String::from("<table>");
 
     // Add rows with board cells and right-side coordinates
     for (row_idx, row) in board.iter().enumerate() {
         html.push_str("<tr>"); // Start a new row
 
-        let mut col_idx = 0;
-        for cell in row {
+        for (col_idx, cell) in row.iter().enumerate() {
             // Determine the class and content based on the cell type
             let (class, content) = match cell.cell_type {
                 TablutCellType::Empty => ("empty", ""),
@@ -48,16 +60,15 @@ pub fn render_tablut_board_as_html(board: &Vec<Vec<TablutCell>>) -> String {
             let throne_class = if cell.is_throne {" throne-cell" } else { "" };
 
             // If the cell is selected, you can add specific styles or content for corners
-            let selected_class = if cell.is_selected {" selected-cell" } else { "" };
+            let selected_class = if selected == Some((row_idx, col_idx)) {" selected-cell" } else { "" };
 
-            let possible_class = if cell.is_possible_move {" possible-cell" } else { "" };
+            let possible_class = if targets.contains(&(row_idx, col_idx)) {" possible-cell" } else { "" };
 
             // Render the cell as an HTML table cell (<td>)
             html.push_str(&format!(
                 r#"<td id="cell-{}-{}" class="{}{}{}{}{}" onclick="handleCellClick({}, {})">{}</td>"#,
                 row_idx, col_idx, class, corner_class, throne_class, selected_class, possible_class, row_idx, col_idx, content
             ));
-            col_idx += 1;
         }
 
         // Add the row number as a right-side coordinate (no border)
@@ -85,15 +96,15 @@ pub fn render_tablut_board_as_html(board: &Vec<Vec<TablutCell>>) -> String {
 
 
 /// Helper function to render the board as an HTML table
-pub fn render_hnefatafl_board_as_html(board: &Vec<Vec<HnefataflCell>>) -> String {
+#[cfg(feature = "hnefatafl")]
+pub fn render_hnefatafl_board_as_html(board: &[Vec<HnefataflCell>], selected: Option<(usize, usize)>, targets: &[(usize, usize)]) -> String {
     let mut html = String::from("<table>");
 
     // Add rows with board cells and right-side coordinates
     for (row_idx, row) in board.iter().enumerate() {
         html.push_str("<tr>"); // Start a new row
 
-        let mut col_idx = 0;
-        for cell in row {
+        for (col_idx, cell) in row.iter().enumerate() {
             // Determine the class and content based on the cell type
             let (class, content) = match cell.cell_type {
                 HnefataflCellType::Empty => ("empty", ""),
@@ -118,16 +129,15 @@ pub fn render_hnefatafl_board_as_html(board: &Vec<Vec<HnefataflCell>>) -> String
             let throne_class = if cell.is_throne {" throne-cell" } else { "" };
 
             // If the cell is selected, you can add specific styles or content for corners
-            let selected_class = if cell.is_selected {" selected-cell" } else { "" };
+            let selected_class = if selected == Some((row_idx, col_idx)) {" selected-cell" } else { "" };
 
-            let possible_class = if cell.is_possible_move {" possible-cell" } else { "" };
+            let possible_class = if targets.contains(&(row_idx, col_idx)) {" possible-cell" } else { "" };
 
             // Render the cell as an HTML table cell (<td>)
             html.push_str(&format!(
                 r#"<td id="cell-{}-{}" class="{}{}{}{}{}" onclick="handleCellClick({}, {})">{}</td>"#,
                 row_idx, col_idx, class, corner_class, throne_class, selected_class, possible_class, row_idx, col_idx, content
             ));
-            col_idx += 1;
         }
 
         // Add the row number as a right-side coordinate (no border)
@@ -153,15 +163,15 @@ pub fn render_hnefatafl_board_as_html(board: &Vec<Vec<HnefataflCell>>) -> String
     html
 }
 
-pub fn render_brandubh_board_as_html(board: &Vec<Vec<BrandubhCell>>) -> String {
+#[cfg(feature = "brandubh")]
+pub fn render_brandubh_board_as_html(board: &[Vec<BrandubhCell>], selected: Option<(usize, usize)>, targets: &[(usize, usize)]) -> String {
     let mut html = String::from("<table>");
 
     // Add rows with board cells and right-side coordinates
     for (row_idx, row) in board.iter().enumerate() {
         html.push_str("<tr>"); // Start a new row
 
-        let mut col_idx = 0;
-        for cell in row {
+        for (col_idx, cell) in row.iter().enumerate() {
             // Determine the class and content based on the cell type
             let (class, content) = match cell.cell_type {
                 BrandubhCellType::Empty => ("empty", ""),
@@ -186,16 +196,15 @@ pub fn render_brandubh_board_as_html(board: &Vec<Vec<BrandubhCell>>) -> String {
             let throne_class = if cell.is_throne {" throne-cell" } else { "" };
 
             // If the cell is selected, you can add specific styles or content for corners
-            let selected_class = if cell.is_selected {" selected-cell" } else { "" };
+            let selected_class = if selected == Some((row_idx, col_idx)) {" selected-cell" } else { "" };
 
-            let possible_class = if cell.is_possible_move {" possible-cell" } else { "" };
+            let possible_class = if targets.contains(&(row_idx, col_idx)) {" possible-cell" } else { "" };
 
             // Render the cell as an HTML table cell (<td>)
             html.push_str(&format!(
                 r#"<td id="cell-{}-{}" class="{}{}{}{}{}" onclick="handleCellClick({}, {})">{}</td>"#,
                 row_idx, col_idx, class, corner_class, throne_class, selected_class, possible_class, row_idx, col_idx, content
             ));
-            col_idx += 1;
         }
 
         // Add the row number as a right-side coordinate (no border)
@@ -221,15 +230,15 @@ pub fn render_brandubh_board_as_html(board: &Vec<Vec<BrandubhCell>>) -> String {
     html
 }
 
-pub fn render_koch_board_as_html(board: &Vec<Vec<KochCell>>) -> String {
+#[cfg(feature = "koch")]
+pub fn render_koch_board_as_html(board: &[Vec<KochCell>], selected: Option<(usize, usize)>, targets: &[(usize, usize)]) -> String {
     let mut html = String::from("<table>");
 
     // Add rows with board cells and right-side coordinates
     for (row_idx, row) in board.iter().enumerate() {
         html.push_str("<tr>"); // Start a new row
 
-        let mut col_idx = 0;
-        for cell in row {
+        for (col_idx, cell) in row.iter().enumerate() {
             // Determine the class and content based on the cell type
             let (class, content) = match cell.cell_type {
                 KochCellType::Empty => ("empty", ""),
@@ -254,16 +263,15 @@ pub fn render_koch_board_as_html(board: &Vec<Vec<KochCell>>) -> String {
             let throne_class = if cell.is_throne {" throne-cell" } else { "" };
 
             // If the cell is selected, you can add specific styles or content for corners
-            let selected_class = if cell.is_selected {" selected-cell" } else { "" };
+            let selected_class = if selected == Some((row_idx, col_idx)) {" selected-cell" } else { "" };
 
-            let possible_class = if cell.is_possible_move {" possible-cell" } else { "" };
+            let possible_class = if targets.contains(&(row_idx, col_idx)) {" possible-cell" } else { "" };
 
             // Render the cell as an HTML table cell (<td>)
             html.push_str(&format!(
                 r#"<td id="cell-{}-{}" class="{}{}{}{}{}" onclick="handleCellClick({}, {})">{}</td>"#,
                 row_idx, col_idx, class, corner_class, throne_class, selected_class, possible_class, row_idx, col_idx, content
             ));
-            col_idx += 1;
         }
 
         // Add the row number as a right-side coordinate (no border)
@@ -290,8 +298,93 @@ pub fn render_koch_board_as_html(board: &Vec<Vec<KochCell>>) -> String {
 }
 
 
+// Render the board of any variant
+pub fn render_game_board_as_html(game: &Game) -> String {
+    render_board_view_as_html(game, None, &[])
+}
+
+// Render the board of any variant as one viewer sees it, with their selected
+// piece and the squares it can move to highlighted
+pub fn render_board_view_as_html(game: &Game, selected: Option<(usize, usize)>, targets: &[(usize, usize)]) -> String {
+    match game {
+        #[cfg(feature = "tablut")]
+        Game::Tablut(state) => render_tablut_board_as_html(&state.board, selected, targets),
+        #[cfg(feature = "brandubh")]
+        Game::Brandubh(state) => render_brandubh_board_as_html(&state.board, selected, targets),
+        #[cfg(feature = "hnefatafl")]
+        Game::Hnefatafl(state) => render_hnefatafl_board_as_html(&state.board, selected, targets),
+        #[cfg(feature = "koch")]
+        Game::Koch(state) => render_koch_board_as_html(&state.board, selected, targets),
+    }
+}
+
+// Render a finished analysis as the summary and the annotated move table
+#[cfg(feature = "bots")]
+pub fn render_analysis_as_html(analysis: &Analysis) -> String {
+    let accuracy = |value: Option<f64>| value.map_or("-".to_string(), |value| format!("{:.1}%", value));
+    let result = match analysis.winner {
+        Some(Side::Attacker) => "Attackers win",
+        Some(Side::Defender) => "Defenders win",
+        None => "Unfinished",
+    };
+
+    let mut html = String::new();
+    html.push_str(&format!(
+        r#"<p>{}</p><p>Attacker accuracy: {}<br>Defender accuracy: {}</p><p class="record">{}</p>"#,
+        result,
+        accuracy(analysis.attacker_accuracy),
+        accuracy(analysis.defender_accuracy),
+        analysis.annotated_record()
+    ));
+
+    html.push_str("<table><tr><th>Ply</th><th>Side</th><th>Move</th><th>Evaluation</th><th>Loss</th><th>Best move</th><th>Notes</th></tr>");
+    for annotated in &analysis.moves {
+        let evaluation = if annotated.eval >= WIN_SCORE {
+            "Attackers won".to_string()
+        } else if annotated.eval <= -WIN_SCORE {
+            "Defenders won".to_string()
+        } else {
+            format!("{:+.2}", annotated.eval as f64 / 100.0)
+        };
+
+        let mut notes = Vec::new();
+        match annotated.judgement {
+            Judgement::Good => {}
+            Judgement::Inaccuracy => notes.push("Inaccuracy"),
+            Judgement::Mistake => notes.push("Mistake"),
+            Judgement::Blunder => notes.push("Blunder"),
+        }
+        if annotated.missed_king_escape {
+            notes.push("Missed king escape");
+        }
+        if annotated.missed_capture {
+            notes.push("Missed capture");
+        }
+        let tablebase_note = annotated.tablebase.map(|outcome| format!("Tablebase: {}", outcome.describe(annotated.side.opponent())));
+        if let Some(note) = &tablebase_note {
+            notes.push(note);
+        }
+
+        html.push_str(&format!(
+            r#"<tr class="{:?}"><td>{}</td><td>{}</td><td>{}{}</td><td>{}</td><td>{}</td><td>{}</td><td>{}</td></tr>"#,
+            annotated.judgement,
+            annotated.ply,
+            annotated.side,
+            annotated.notation,
+            annotated.judgement.symbol(),
+            evaluation,
+            annotated.loss,
+            annotated.best_move.as_deref().unwrap_or(""),
+            notes.join(", ")
+        ));
+    }
+    html.push_str("</table>");
+    html
+}
+
 
 // Helper to get the session ID from the cookie
+#[cfg(feature = "server")]
 pub fn get_session_id_from_cookie(headers: &warp::http::HeaderMap) -> Option<String> {
     headers
         .get("cookie")
@@ -301,12 +394,8 @@ pub fn get_session_id_from_cookie(headers: &warp::http::HeaderMap) -> Option<Str
                 .split(';')
                 .find_map(|cookie| {
                     let cookie = cookie.trim();
-                    if cookie.starts_with("session_id=") {
-                        // Safe extraction after the "=" symbol
-                        Some(cookie["session_id=".len()..].to_string()) // Extract session ID
-                    } else {
-                        None
-                    }
+                    // Safe extraction after the "=" symbol
+                    cookie.strip_prefix("session_id=").map(|id| id.to_string()) // Extract session ID
                 })
         })
 }
@@ -1,8 +1,10 @@
-#[warn(unused_variables)]
+#![warn(unused_variables)]
 
 use serde::{Deserialize, Serialize};
 use std::fmt;
 
+use crate::zobrist;
+
 #[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq)]
 pub enum CellType {
     Empty,
@@ -16,8 +18,6 @@ pub struct Cell {
     pub cell_type: CellType,
     pub is_corner: bool,
     pub is_throne: bool,
-    pub is_selected: bool,
-    pub is_possible_move: bool,
 }
 
 
@@ -44,28 +44,52 @@ impl fmt::Display for Cell {
         if self.is_throne {
             display_str.push_str(" (Throne)");
         }
-        if self.is_selected {
-            display_str.push_str(" (Selected)");
-        }
 
         // Write the final string to the formatter
         write!(f, "{}", display_str)
     }
 }
 
+/// A captured piece together with the square it was taken from
+pub type Capture = ((usize, usize), Cell);
+
+/// Everything needed to take back a move made with `make_move`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct UndoRecord {
+    pub from: (usize, usize),               // Origin square of the move
+    pub to: (usize, usize),                 // Destination square of the move
+    pub from_cell: Cell,                    // Origin cell before the move
+    pub to_cell: Cell,                      // Destination cell before the move
+    pub captured: Vec<Capture>,             // Pieces removed by the move
+    pub previous_turn: Cell,                // Side to move before the move
+    pub previous_game_over: bool,           // Game-over flag before the move
+    pub previous_winner: Option<Cell>,      // Winner before the move
+    pub previous_move_done: bool,           // Move-done flag before the move
+    pub previous_hash: u64,                 // Position hash before the move
+}
+
+/// Maps a piece to its index in the Zobrist key table
+fn zobrist_piece(cell_type: CellType) -> Option<usize> {
+    match cell_type {
+        CellType::Empty => None,
+        CellType::Attacker => Some(zobrist::ATTACKER),
+        CellType::Defender => Some(zobrist::DEFENDER),
+        CellType::King => Some(zobrist::KING),
+    }
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct GameState {
     pub board: Vec<Vec<Cell>>, // 2D grid representing the board
     pub current_turn: Cell,    // Attacker or Defender
     pub game_over: bool,       // Indicates if the game has ended
     pub winner: Option<Cell>,  // Stores the winner (None if ongoing)
-    pub click_count: u32,      // Number of clicks
-    pub from: (usize, usize),  // From position
     pub board_message: String, // Message to display on the board
     pub game_title: String,    // Game title
-    pub last_click: (usize, usize), // Last clicked cell
     pub id: usize,
     pub move_done: bool,
+    pub undo_stack: Vec<UndoRecord>, // Moves made so far, most recent last
+    pub hash: u64,                   // Zobrist hash of piece placement and side to move
 }
 
 impl GameState {
@@ -77,8 +101,6 @@ impl GameState {
                     cell_type: CellType::Empty,
                     is_corner: false,
                     is_throne: false,
-                    is_selected: false,
-                    is_possible_move: false,
                 }; 11
             ];
             11
@@ -99,8 +121,6 @@ impl GameState {
                 cell_type: CellType::Attacker,
                 is_corner: false,
                 is_throne: false,
-                is_selected: false,
-                is_possible_move: false,  
             };
         }
 
@@ -116,8 +136,6 @@ impl GameState {
                 cell_type: CellType::Defender,
                 is_corner: false,
                 is_throne: false,
-                is_selected: false,
-                is_possible_move: false,
             };
         }
 
@@ -129,8 +147,6 @@ impl GameState {
                 cell_type: CellType::Empty, // Corner is empty but still marked as a corner
                 is_corner: true,
                 is_throne: false,
-                is_selected: false,
-                is_possible_move: false,
             };
         }
 
@@ -139,173 +155,29 @@ impl GameState {
             cell_type: CellType::King,
             is_corner: false,
             is_throne: true,
-            is_selected: false,
-            is_possible_move: false,
         };
 
-        // Return the GameState instance
-        GameState {
+        // Return the GameState instance, hashing the starting position
+        let mut state = GameState {
             board,
             current_turn: Cell {
                 cell_type: CellType::Attacker,
                 is_corner: false,
                 is_throne: false,
-                is_selected: false,
-                is_possible_move: false,
             },
             game_over: false,
             winner: None,
-            click_count: 1,
-            from: (0, 0),
             board_message: "Current turn: Attacker".to_string(),
             game_title: "Hnefatafl".to_string(),
-            last_click: (0, 0),
-            id: id,
+            id,
             move_done: false,
-        }
+            undo_stack: Vec::new(),
+            hash: 0,
+        };
+        state.hash = state.compute_hash();
+        state
     }
     
-    pub fn process_click(&mut self, row: usize, col: usize) -> Result<(), String> {
-        // Validate and process the click based on the game state
-        if row >= self.board.len() || col >= self.board[0].len() {
-            return Err("Invalid cell coordinates.".to_string());
-        }
-
-        if self.game_over {
-            return Err("Game is already over.".to_string());
-        }
-        
-        self.board[self.last_click.0][self.last_click.1].is_selected = false;   // Deselect last clicked cell
-        self.board[row][col].is_selected = true;                                // Select the clicked cell
-        let clicked_cell = &self.board[row][col];                        // Get the clicked cell
-        self.last_click = (row, col);                                           // Update the last clicked cell
-
-    
-        if self.click_count % 2 == 1 {
-            // First click: Select a piece to move
-            if clicked_cell.cell_type == CellType::Empty {
-                self.board[self.last_click.0][self.last_click.1].is_selected = false;
-                for cell in self.board.iter_mut().flat_map(|r| r.iter_mut()) {
-                        cell.is_possible_move = false;
-                    }
-                return Ok(());
-            }
-            else if clicked_cell.cell_type == CellType::Defender && self.current_turn.cell_type == CellType::Attacker {
-                self.board[self.last_click.0][self.last_click.1].is_selected = false;
-                for cell in self.board.iter_mut().flat_map(|r| r.iter_mut()) {
-                        cell.is_possible_move = false;
-                    }
-                return Ok(());
-            }
-            else if clicked_cell.cell_type == CellType::King && self.current_turn.cell_type == CellType::Attacker {
-                self.board[self.last_click.0][self.last_click.1].is_selected = false;
-                for cell in self.board.iter_mut().flat_map(|r| r.iter_mut()) {
-                        cell.is_possible_move = false;
-                    }
-                return Ok(());
-            }
-            else if clicked_cell.cell_type == CellType::Attacker && self.current_turn.cell_type == CellType::Defender {
-                self.board[self.last_click.0][self.last_click.1].is_selected = false;
-                for cell in self.board.iter_mut().flat_map(|r| r.iter_mut()) {
-                        cell.is_possible_move = false;
-                    }
-                return Ok(());
-            }
-            else {
-                self.click_count += 1;
-                self.from = (row, col);
-
-                let possible_moves = self.calculate_valid_moves(self.from);
-
-                for cell in possible_moves {
-                    self.board[cell.0][cell.1].is_possible_move = true;
-                }
-                self.move_done = false;
-            }
-        } else {
-            // Second click: Select an empty cell to move to
-            if clicked_cell.cell_type == CellType::Defender && self.current_turn.cell_type == CellType::Attacker {
-                self.board[self.last_click.0][self.last_click.1].is_selected = false;
-                for cell in self.board.iter_mut().flat_map(|r| r.iter_mut()) {
-                        cell.is_possible_move = false;
-                    }
-                return Ok(());
-            }
-            else if clicked_cell.cell_type == CellType::King && self.current_turn.cell_type == CellType::Attacker {
-                self.board[self.last_click.0][self.last_click.1].is_selected = false;
-                for cell in self.board.iter_mut().flat_map(|r| r.iter_mut()) {
-                        cell.is_possible_move = false;
-                    }
-                return Ok(());
-            }
-            else if clicked_cell.cell_type == CellType::Attacker && self.current_turn.cell_type == CellType::Defender {
-                self.board[self.last_click.0][self.last_click.1].is_selected = false;
-                for cell in self.board.iter_mut().flat_map(|r| r.iter_mut()) {
-                        cell.is_possible_move = false;
-                    }
-                return Ok(());
-            }
-            else if clicked_cell.cell_type != CellType::Empty {
-
-                if self.board[self.last_click.0][self.last_click.1].cell_type == clicked_cell.cell_type {
-                    for cell in self.board.iter_mut().flat_map(|r| r.iter_mut()) {
-                        cell.is_possible_move = false;
-                    }
-                    self.board[self.last_click.0][self.last_click.1].is_selected = false;  
-                    self.board[row][col].is_selected = true;                                                    
-                    self.last_click = (row, col);
-                    self.from = (row, col);
-                    let possible_moves = self.calculate_valid_moves(self.from);
-
-                    for cell in possible_moves {
-                        self.board[cell.0][cell.1].is_possible_move = true;
-                    }
-                    self.move_done = false;
-                } else {
-                    self.click_count -= 1;
-                    self.board[self.last_click.0][self.last_click.1].is_selected = false;
-                    for cell in self.board.iter_mut().flat_map(|r| r.iter_mut()) {
-                            cell.is_possible_move = false;
-                        }
-                }
-                
-                return Ok(());
-            }
-            else if clicked_cell.is_corner && self.board[self.from.0][self.from.1].cell_type != CellType::King {
-                self.click_count -= 1;
-                self.board[self.last_click.0][self.last_click.1].is_selected = false;
-                for cell in self.board.iter_mut().flat_map(|r| r.iter_mut()) {
-                        cell.is_possible_move = false;
-                    }
-                return Ok(());
-            }
-            else if clicked_cell.is_throne && self.board[self.from.0][self.from.1].cell_type != CellType::King {
-                self.click_count -= 1;
-                self.board[self.last_click.0][self.last_click.1].is_selected = false;
-                for cell in self.board.iter_mut().flat_map(|r| r.iter_mut()) {
-                        cell.is_possible_move = false;
-                    }
-                return Ok(());
-            }
-            else {
-                // Make the move
-                match self.make_move(self.from, (row, col)) {
-                    Ok(_) => {
-                        self.click_count += 1;
-
-                        for cell in self.board.iter_mut().flat_map(|r| r.iter_mut()) {
-                            cell.is_possible_move = false;
-                        }
-                    }
-                    Err(error_message) => {
-                        return Err(error_message.to_string());
-                    }
-                }
-            }
-        }
-        Ok(())
-    }
-
     pub fn calculate_valid_moves(&self, start: (usize, usize)) -> Vec<(usize, usize)> {
         let mut valid_moves = Vec::new();
         let (start_row, start_col) = start;
@@ -367,15 +239,25 @@ impl GameState {
     pub fn make_move(&mut self, from: (usize, usize), to: (usize, usize)) -> Result<(), String> {    
         // Validate the move
         if !self.is_valid_move(from, to) {
-            self.board[self.last_click.0][self.last_click.1].is_selected = false;
-            for cell in self.board.iter_mut().flat_map(|r| r.iter_mut()) {
-                    cell.is_possible_move = false;
-                }
             return Ok(());
         }
+
+        // Remember what the move changes so it can be taken back
+        let mut record = UndoRecord {
+            from,
+            to,
+            from_cell: self.board[from.0][from.1],
+            to_cell: self.board[to.0][to.1],
+            captured: Vec::new(),
+            previous_turn: self.current_turn,
+            previous_game_over: self.game_over,
+            previous_winner: self.winner,
+            previous_move_done: self.move_done,
+            previous_hash: self.hash,
+        };
     
         // Make the move
-        let mut moved_piece = self.board[from.0][from.1].clone();
+        let mut moved_piece = self.board[from.0][from.1];
         if moved_piece.is_throne {
             moved_piece.is_throne = false;
         }
@@ -397,29 +279,34 @@ impl GameState {
                 cell_type: CellType::Empty,
                 is_corner: false,
                 is_throne: true,
-                is_selected: false,
-                is_possible_move: false,
             };
         } else if self.board[from.0][from.1].is_corner {
             self.board[from.0][from.1] = Cell {
                 cell_type: CellType::Empty,
                 is_corner: true,
                 is_throne: false,
-                is_selected: false,
-                is_possible_move: false,
             };
         } else {
             self.board[from.0][from.1] = Cell {
                 cell_type: CellType::Empty,
                 is_corner: false,
                 is_throne: false,
-                is_selected: false,
-                is_possible_move: false,
             };
         }
     
         // Check for captures at the new position
-        self.check_captures(to)?;
+        record.captured = self.check_captures(to)?;
+
+        // Update the position hash for the moved and captured pieces
+        if let Some(piece) = zobrist_piece(record.from_cell.cell_type) {
+            self.hash ^= zobrist::piece_key(piece, from) ^ zobrist::piece_key(piece, to);
+        }
+        for &(pos, cell) in &record.captured {
+            if let Some(piece) = zobrist_piece(cell.cell_type) {
+                self.hash ^= zobrist::piece_key(piece, pos);
+            }
+        }
+        self.undo_stack.push(record);
     
         // Check win conditions
         if let Some(winner) = self.check_win_condition() {
@@ -435,21 +322,19 @@ impl GameState {
                     cell_type: CellType::Defender,
                     is_corner: false,
                     is_throne: false,
-                    is_selected: false,
-                    is_possible_move: false,
                 };
                 self.board_message = "Current turn: Defender".to_string();
                 self.move_done = true;
+                self.hash ^= zobrist::side_key();
             } else {
                 self.current_turn =  Cell {
                     cell_type: CellType::Attacker,
                     is_corner: false,
                     is_throne: false,
-                    is_selected: false,
-                    is_possible_move: false,
                 };
                 self.board_message = "Current turn: Attacker".to_string();
                 self.move_done = true;
+                self.hash ^= zobrist::side_key();
             };
         }
     
@@ -458,7 +343,53 @@ impl GameState {
     
        
 
-    pub fn check_captures(&mut self, pos: (usize, usize)) -> Result<(), String> {
+    /// Takes back the last move made with `make_move`, restoring captured pieces,
+    /// the side to move and the game-over status.
+    pub fn unmake(&mut self) -> Result<(), String> {
+        let record = match self.undo_stack.pop() {
+            Some(record) => record,
+            None => return Err("No move to undo.".to_string()),
+        };
+
+        // Put the moved piece back and restore any captured pieces
+        self.board[record.from.0][record.from.1] = record.from_cell;
+        self.board[record.to.0][record.to.1] = record.to_cell;
+        for &((row, col), cell) in &record.captured {
+            self.board[row][col] = cell;
+        }
+
+        // Restore the turn and the game status
+        self.current_turn = record.previous_turn;
+        self.game_over = record.previous_game_over;
+        self.winner = record.previous_winner;
+        self.move_done = record.previous_move_done;
+        self.hash = record.previous_hash;
+        self.board_message = match self.winner {
+            Some(winner) => format!("{} wins!", winner),
+            None => format!("Current turn: {}", self.current_turn.cell_type),
+        };
+
+        Ok(())
+    }
+
+    /// Computes the Zobrist hash of the current position from scratch.
+    pub fn compute_hash(&self) -> u64 {
+        let mut hash = 0;
+        for (r, row) in self.board.iter().enumerate() {
+            for (c, cell) in row.iter().enumerate() {
+                if let Some(piece) = zobrist_piece(cell.cell_type) {
+                    hash ^= zobrist::piece_key(piece, (r, c));
+                }
+            }
+        }
+        if self.current_turn.cell_type == CellType::Defender {
+            hash ^= zobrist::side_key();
+        }
+        hash
+    }
+
+    /// Removes the pieces captured by the piece that just moved to `pos` and returns them.
+    pub fn check_captures(&mut self, pos: (usize, usize)) -> Result<Vec<Capture>, String> {
         let neighbors = [
             (pos.0.wrapping_sub(1), pos.1), // Up
             (pos.0 + 1, pos.1), // Down
@@ -466,7 +397,8 @@ impl GameState {
             (pos.0, pos.1 + 1), // Right
         ];
     
-        let cell = self.board[pos.0][pos.1].clone(); // Clone the current cell (with cell_type and is_corner)
+        let cell = self.board[pos.0][pos.1]; // Clone the current cell (with cell_type and is_corner)
+        let mut captured = Vec::new();
     
         for (i, &(nx, ny)) in neighbors.iter().enumerate() {
             if self.is_within_bounds((nx, ny)) {
@@ -496,12 +428,11 @@ impl GameState {
                             || self.board[nnx][nny].is_throne)
                     {
                         // Capture the opponent's piece by setting it to Empty
+                        captured.push(((nx, ny), self.board[nx][ny]));
                         self.board[nx][ny] = Cell {
                             cell_type: CellType::Empty,
                             is_corner: false, // Reset the corner status after capture
                             is_throne: false,
-                            is_selected: false,
-                            is_possible_move: false,
                         };
                     }
                 } else {
@@ -513,12 +444,11 @@ impl GameState {
                             || (self.board[nnx][nny].is_throne && self.board[nnx][nny].cell_type == CellType::Empty))
                     {
                         // Capture the opponent's piece by setting it to Empty
+                        captured.push(((nx, ny), self.board[nx][ny]));
                         self.board[nx][ny] = Cell {
                             cell_type: CellType::Empty,
                             is_corner: false, // Reset the corner status after capture
                             is_throne: false,
-                            is_selected: false,
-                            is_possible_move: false,
                         };
                     }
                 }
@@ -527,7 +457,7 @@ impl GameState {
             }
         }
     
-        Ok(())
+        Ok(captured)
     }
 
 
@@ -591,8 +521,6 @@ impl GameState {
                 cell_type: CellType::Defender,
                 is_corner: false, // This is up to your game logic to define
                 is_throne: false,
-                is_selected: false,
-                is_possible_move: false,
             });
         }
     
@@ -619,8 +547,6 @@ impl GameState {
                     cell_type: CellType::Attacker,
                     is_corner: false,
                     is_throne: false,
-                    is_selected: false,
-                    is_possible_move: false,
                 }); // Attackers win
             }
         }
@@ -642,8 +568,6 @@ impl GameState {
                 cell_type: CellType::Attacker,
                 is_corner: false,
                 is_throne: false,
-                is_selected: false,
-                is_possible_move: false,
             }); // Attackers win
         }
     
@@ -652,3 +576,14 @@ impl GameState {
     
     
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::engine::{unmake_tests, Side};
+
+    unmake_tests!(unmake_restores_the_game, Hnefatafl, [
+        ("11/11/9k1/11/11/11/11/3da6/11/11/2a8 a", "c1-c4", 1, None),
+        ("11/11/11/7d3/11/11/11/3a7/2aka6/11/3a7 a", "d1-d2", 0, Some(Side::Attacker)),
+        ("11/11/11/7d3/11/11/10k/11/11/1a9/11 d", "k5-k11", 0, Some(Side::Defender)),
+    ]);
+}
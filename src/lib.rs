@@ -0,0 +1,67 @@
+//! Tafl rules engine, computer opponents and offline tools, with the web
+//! server behind the `server` feature (enabled by default). Build with
+//! `default-features = false` to use the engine without warp and tokio.
+//!
+//! Each variant has its own feature (`tablut`, `brandubh`, `hnefatafl`,
+//! `koch`), as do the computer opponents and tools (`bots`) and the HTML
+//! rendering (`rendering`); `server` needs both. `Variant::ALL` lists the
+//! variants compiled in, e.g. with
+//! `default-features = false, features = ["brandubh"]` only Brandubh.
+
+#![warn(unused_variables)]
+#![recursion_limit = "256"] // The chain of warp route filters is deeply nested
+
+#[cfg(not(any(feature = "tablut", feature = "brandubh", feature = "hnefatafl", feature = "koch")))]
+compile_error!("Enable at least one variant feature: tablut, brandubh, hnefatafl or koch");
+
+// Rules of each variant and the common engine interface
+#[cfg(feature = "tablut")]
+pub mod tablut;
+#[cfg(feature = "hnefatafl")]
+pub mod hnefatafl;
+#[cfg(feature = "brandubh")]
+pub mod brandubh;
+#[cfg(feature = "koch")]
+pub mod koch;
+pub mod zobrist;
+pub mod engine;
+
+// Computer opponents
+#[cfg(feature = "bots")]
+pub mod eval;
+#[cfg(feature = "bots")]
+pub mod search;
+#[cfg(feature = "bots")]
+pub mod mcts;
+#[cfg(feature = "bots")]
+pub mod bot;
+#[cfg(feature = "bots")]
+pub mod external;
+#[cfg(feature = "bots")]
+pub mod book;
+#[cfg(feature = "bots")]
+pub mod tablebase;
+#[cfg(feature = "bots")]
+pub mod analysis;
+
+// Game records and offline tools
+#[cfg(feature = "bots")]
+pub mod archive;
+pub mod perft;
+#[cfg(feature = "bots")]
+pub mod tune;
+#[cfg(feature = "bots")]
+pub mod tournament;
+#[cfg(feature = "bots")]
+pub mod simulate;
+pub mod cli;
+
+// HTML rendering of boards and analyses
+#[cfg(feature = "rendering")]
+pub mod helper_functions;
+
+#[cfg(feature = "server")]
+pub mod server;
+
+#[cfg(test)]
+mod conformance;
@@ -0,0 +1,175 @@
+// Monte Carlo tree search (UCT) with random playouts. Playouts that run too
+// long are scored with the static evaluation instead of being played out.
+
+use rand::rngs::StdRng;
+use rand::seq::SliceRandom;
+use rand::SeedableRng;
+use std::time::{Duration, Instant};
+
+use crate::engine::{Move, Rules, Side};
+use crate::eval::{evaluate_for_attacker, Weights};
+
+/// Playout length after which the position is scored by the evaluation
+const MAX_PLAYOUT_PLIES: usize = 120;
+
+/// Scale of the evaluation when turning it into a win probability
+const EVAL_SCALE: f64 = 400.0;
+
+#[derive(Debug, Clone, Copy)]
+pub struct MctsLimits {
+    pub playouts: u32,
+    pub exploration: f64,   // UCT exploration constant
+    pub time_limit: Duration,
+}
+
+struct Node {
+    mv: Option<Move>,       // Move leading to this node (None at the root)
+    parent: Option<usize>,
+    children: Vec<usize>,
+    untried: Vec<Move>,
+    visits: u32,
+    wins: f64,              // Sum of results for the side that played `mv`
+    mover: Side,            // Side that played `mv`
+}
+
+pub struct MctsSearcher {
+    weights: Weights,
+    rng: StdRng,
+}
+
+impl MctsSearcher {
+    pub fn new(weights: Weights) -> Self {
+        MctsSearcher {
+            weights,
+            rng: StdRng::from_entropy(),
+        }
+    }
+
+    /// Runs playouts from the position and returns the most visited move.
+    pub fn search<G: Rules>(&mut self, game: &G, limits: &MctsLimits) -> Option<Move> {
+        let mut game = game.clone();
+        let deadline = Instant::now() + limits.time_limit;
+        let root_side = game.side_to_move();
+
+        let mut nodes = vec![Node {
+            mv: None,
+            parent: None,
+            children: Vec::new(),
+            untried: game.legal_moves(),
+            visits: 0,
+            wins: 0.0,
+            mover: root_side.opponent(),
+        }];
+        if nodes[0].untried.len() <= 1 {
+            return nodes[0].untried.first().copied();
+        }
+
+        for playout in 0..limits.playouts {
+            if playout.is_multiple_of(64) && Instant::now() >= deadline {
+                break;
+            }
+
+            // Selection: descend through fully expanded nodes by UCT score
+            let mut node = 0;
+            let mut depth = 0;
+            while nodes[node].untried.is_empty() && !nodes[node].children.is_empty() {
+                node = self.select_child(&nodes, node, limits.exploration);
+                if game.play(nodes[node].mv.expect("child nodes have a move")).is_err() {
+                    break;
+                }
+                depth += 1;
+            }
+
+            // Expansion: add one untried move as a new child
+            if !nodes[node].untried.is_empty() && game.winner().is_none() {
+                let index = rand::Rng::gen_range(&mut self.rng, 0..nodes[node].untried.len());
+                let mv = nodes[node].untried.swap_remove(index);
+                let mover = game.side_to_move();
+                if game.play(mv).is_ok() {
+                    depth += 1;
+                    nodes.push(Node {
+                        mv: Some(mv),
+                        parent: Some(node),
+                        children: Vec::new(),
+                        untried: game.legal_moves(),
+                        visits: 0,
+                        wins: 0.0,
+                        mover,
+                    });
+                    let child = nodes.len() - 1;
+                    nodes[node].children.push(child);
+                    node = child;
+                }
+            }
+
+            // Simulation: random moves until the game ends or runs too long
+            let attacker_result = self.playout(&mut game);
+            for _ in 0..depth {
+                let _ = game.undo();
+            }
+
+            // Backpropagation
+            let mut current = Some(node);
+            while let Some(index) = current {
+                let entry = &mut nodes[index];
+                entry.visits += 1;
+                entry.wins += match entry.mover {
+                    Side::Attacker => attacker_result,
+                    Side::Defender => 1.0 - attacker_result,
+                };
+                current = entry.parent;
+            }
+        }
+
+        nodes[0]
+            .children
+            .iter()
+            .max_by_key(|&&child| nodes[child].visits)
+            .and_then(|&child| nodes[child].mv)
+    }
+
+    fn select_child(&self, nodes: &[Node], parent: usize, exploration: f64) -> usize {
+        let log_visits = (nodes[parent].visits.max(1) as f64).ln();
+        *nodes[parent]
+            .children
+            .iter()
+            .max_by(|&&a, &&b| {
+                let score = |child: usize| {
+                    let node = &nodes[child];
+                    let visits = node.visits.max(1) as f64;
+                    node.wins / visits + exploration * (log_visits / visits).sqrt()
+                };
+                score(a).total_cmp(&score(b))
+            })
+            .expect("selection only descends into nodes with children")
+    }
+
+    /// Plays random moves and returns the result for the attackers
+    /// (1 for a win, 0 for a loss), undoing the playout afterwards.
+    fn playout<G: Rules>(&mut self, game: &mut G) -> f64 {
+        let mut plies = 0;
+        let result = loop {
+            if let Some(winner) = game.winner() {
+                break if winner == Side::Attacker { 1.0 } else { 0.0 };
+            }
+            if plies >= MAX_PLAYOUT_PLIES {
+                let score = evaluate_for_attacker(game, &self.weights) as f64;
+                break 1.0 / (1.0 + (-score / EVAL_SCALE).exp());
+            }
+            let moves = game.legal_moves();
+            let mv = match moves.choose(&mut self.rng) {
+                Some(&mv) => mv,
+                // The side to move is stuck and loses
+                None => break if game.side_to_move() == Side::Attacker { 0.0 } else { 1.0 },
+            };
+            if game.play(mv).is_err() {
+                break 0.5;
+            }
+            plies += 1;
+        };
+        for _ in 0..plies {
+            let _ = game.undo();
+        }
+        result
+    }
+}
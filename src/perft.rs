@@ -0,0 +1,76 @@
+// Move generation counters used to regression-check the rules engines.
+
+use crate::engine::{position_string, Move, Rules};
+
+/// Counts the leaf positions reachable in exactly `depth` moves.
+/// Positions where the game ends early do not count as leaves.
+pub fn perft<G: Rules>(game: &mut G, depth: u32) -> u64 {
+    if depth == 0 {
+        return 1;
+    }
+
+    let moves = game.legal_moves();
+    if depth == 1 {
+        return moves.len() as u64;
+    }
+
+    let mut nodes = 0;
+    for mv in moves {
+        if game.play(mv).is_ok() {
+            nodes += perft(game, depth - 1);
+            let _ = game.undo();
+        }
+    }
+    nodes
+}
+
+/// Like `perft`, but reports the leaf count below each root move.
+pub fn divide<G: Rules>(game: &mut G, depth: u32) -> Vec<(Move, u64)> {
+    let mut counts = Vec::new();
+    if depth == 0 {
+        return counts;
+    }
+
+    for mv in game.legal_moves() {
+        if game.play(mv).is_ok() {
+            counts.push((mv, perft(game, depth - 1)));
+            let _ = game.undo();
+        }
+    }
+    counts
+}
+
+/// Walks the move tree like `perft` while checking that every generated move
+/// can be played, that the incremental hash matches a full recomputation and
+/// that undoing a move restores the exact previous position.
+pub fn self_check<G: Rules>(game: &mut G, depth: u32) -> Result<u64, String> {
+    if game.position_hash() != game.recompute_hash() {
+        return Err(format!("Hash mismatch in position {}", position_string(game)));
+    }
+    if depth == 0 {
+        return Ok(1);
+    }
+
+    let mut nodes = 0;
+    for mv in game.legal_moves() {
+        let before = position_string(game);
+        let hash_before = game.position_hash();
+        let winner_before = game.winner();
+        let size = game.size();
+
+        game.play(mv)
+            .map_err(|error| format!("Generated move {} failed in {}: {}", mv.notation(size), before, error))?;
+        nodes += self_check(game, depth - 1)?;
+        game.undo()?;
+
+        if position_string(game) != before || game.position_hash() != hash_before || game.winner() != winner_before {
+            return Err(format!(
+                "Undoing {} from {} left {}",
+                mv.notation(size),
+                before,
+                position_string(game)
+            ));
+        }
+    }
+    Ok(nodes)
+}
@@ -0,0 +1,350 @@
+// Iterative-deepening alpha-beta search with a transposition table and
+// killer/history move ordering.
+
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+use crate::engine::{Move, Rules, Side};
+use crate::eval::{evaluate_for_attacker, Weights, WIN_SCORE};
+
+const TT_SIZE: usize = 1 << 18;
+const INFINITY: i32 = WIN_SCORE + 1_000;
+const MAX_PLY: usize = 64;
+
+/// Scores at least this far from zero are forced wins or losses
+const MATE_BOUND: i32 = WIN_SCORE - MAX_PLY as i32;
+
+/// How long and how deep a search may run
+#[derive(Debug, Clone, Copy)]
+pub struct SearchLimits {
+    pub max_depth: u32,
+    pub time_limit: Duration,
+    pub eval_noise: i32,     // Largest random offset added to leaf evaluations
+}
+
+#[derive(Debug, Clone)]
+pub struct SearchResult {
+    pub best_move: Option<Move>,
+    pub score: i32,          // From the point of view of the side to move
+    pub depth: u32,          // Deepest fully searched iteration
+    pub nodes: u64,
+    pub pv: Vec<Move>,       // Expected line of play, starting with best_move
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Bound {
+    Exact,
+    Lower,
+    Upper,
+}
+
+#[derive(Clone, Copy)]
+struct TtEntry {
+    key: u64,
+    depth: u32,
+    score: i32,
+    bound: Bound,
+    best_move: Option<Move>,
+}
+
+/// Converts a score counting a forced result from the root into one counting
+/// it from the node, so the entry holds wherever the position is reached.
+fn score_to_tt(score: i32, ply: usize) -> i32 {
+    if score >= MATE_BOUND {
+        score + ply as i32
+    } else if score <= -MATE_BOUND {
+        score - ply as i32
+    } else {
+        score
+    }
+}
+
+/// Converts a score read from the transposition table back into one counting
+/// a forced result from the root of the current search.
+fn score_from_tt(score: i32, ply: usize) -> i32 {
+    if score >= MATE_BOUND {
+        score - ply as i32
+    } else if score <= -MATE_BOUND {
+        score + ply as i32
+    } else {
+        score
+    }
+}
+
+pub struct Searcher {
+    weights: Weights,
+    tt: Vec<Option<TtEntry>>,
+    killers: Vec<[Option<Move>; 2]>,
+    history: HashMap<Move, i32>,
+    nodes: u64,
+    deadline: Instant,
+    stopped: bool,
+    noise: i32,
+    noise_seed: u64,
+    root_exclusions: Vec<Move>,  // Root moves left out, for multi-line searches
+}
+
+impl Searcher {
+    pub fn new(weights: Weights) -> Self {
+        Searcher {
+            weights,
+            tt: vec![None; TT_SIZE],
+            killers: vec![[None; 2]; MAX_PLY],
+            history: HashMap::new(),
+            nodes: 0,
+            deadline: Instant::now(),
+            stopped: false,
+            noise: 0,
+            noise_seed: 0,
+            root_exclusions: Vec::new(),
+        }
+    }
+
+    /// Searches the position with iterative deepening until the depth or
+    /// time limit is reached, returning the result of the last completed depth.
+    pub fn search<G: Rules>(&mut self, game: &G, limits: &SearchLimits) -> SearchResult {
+        let mut game = game.clone();
+        let side = game.side_to_move();
+        self.nodes = 0;
+        self.stopped = false;
+        self.deadline = Instant::now() + limits.time_limit;
+        self.killers = vec![[None; 2]; MAX_PLY];
+        if limits.eval_noise != self.noise || limits.eval_noise > 0 {
+            // Noisy scores from an earlier search must not be reused
+            self.tt = vec![None; TT_SIZE];
+            self.noise = limits.eval_noise;
+            self.noise_seed = rand::random();
+        }
+
+        let mut result = SearchResult {
+            best_move: game.legal_moves().into_iter().find(|mv| !self.root_exclusions.contains(mv)),
+            score: 0,
+            depth: 0,
+            nodes: 0,
+            pv: Vec::new(),
+        };
+
+        for depth in 1..=limits.max_depth.max(1) {
+            let score = self.negamax(&mut game, depth, 0, -INFINITY, INFINITY, side);
+            if self.stopped {
+                break;
+            }
+
+            let pv = self.principal_variation(&mut game, depth);
+            if let Some(&best_move) = pv.first() {
+                result.best_move = Some(best_move);
+            }
+            result.score = score;
+            result.depth = depth;
+            result.pv = pv;
+
+            // No point searching deeper once a forced win or loss is found
+            if score.abs() >= MATE_BOUND {
+                break;
+            }
+        }
+
+        result.nodes = self.nodes;
+        result
+    }
+
+    /// Finds the best `count` lines of play, searching each with an equal
+    /// share of the time limit while leaving out the root moves of the lines
+    /// already found. Lines are sorted from best to worst.
+    pub fn search_lines<G: Rules>(&mut self, game: &G, limits: &SearchLimits, count: usize) -> Vec<SearchResult> {
+        let limits = SearchLimits {
+            time_limit: limits.time_limit / count.max(1) as u32,
+            ..*limits
+        };
+        let mut lines: Vec<SearchResult> = Vec::new();
+        for _ in 0..count {
+            self.root_exclusions = lines.iter().filter_map(|line| line.best_move).collect();
+            let result = self.search(game, &limits);
+            match result.best_move {
+                Some(mv) if !self.root_exclusions.contains(&mv) => lines.push(result),
+                _ => break,
+            }
+        }
+        self.root_exclusions.clear();
+        lines.sort_by_key(|line| -line.score);
+        lines
+    }
+
+    fn negamax<G: Rules>(&mut self, game: &mut G, depth: u32, ply: usize, mut alpha: i32, beta: i32, side: Side) -> i32 {
+        self.nodes += 1;
+        if self.nodes.is_multiple_of(2048) && Instant::now() >= self.deadline {
+            self.stopped = true;
+        }
+        if self.stopped {
+            return 0;
+        }
+
+        // The side to move is not switched when a game ends, so the winner
+        // is scored against the side passed down the tree
+        if let Some(winner) = game.winner() {
+            let score = WIN_SCORE - ply as i32;
+            return if winner == side { score } else { -score };
+        }
+
+        if depth == 0 || ply >= MAX_PLY - 1 {
+            let score = evaluate_for_attacker(game, &self.weights) + self.noise_for(game.position_hash());
+            return if side == Side::Attacker { score } else { -score };
+        }
+
+        let key = game.position_hash();
+        let index = (key as usize) & (TT_SIZE - 1);
+        let mut tt_move = None;
+        if let Some(entry) = self.tt[index] {
+            if entry.key == key {
+                tt_move = entry.best_move;
+                if entry.depth >= depth && ply > 0 {
+                    let score = score_from_tt(entry.score, ply);
+                    match entry.bound {
+                        Bound::Exact => return score,
+                        Bound::Lower if score >= beta => return score,
+                        Bound::Upper if score <= alpha => return score,
+                        _ => {}
+                    }
+                }
+            }
+        }
+
+        let mut moves = game.legal_moves();
+        if moves.is_empty() {
+            // No legal move loses the game
+            return -(WIN_SCORE - ply as i32);
+        }
+        self.order_moves(&mut moves, tt_move, ply);
+
+        let original_alpha = alpha;
+        let mut best_score = -INFINITY;
+        let mut best_move = None;
+
+        for mv in moves {
+            if ply == 0 && self.root_exclusions.contains(&mv) {
+                continue;
+            }
+            if game.play(mv).is_err() {
+                continue;
+            }
+            let score = -self.negamax(game, depth - 1, ply + 1, -beta, -alpha, side.opponent());
+            let _ = game.undo();
+            if self.stopped {
+                return 0;
+            }
+
+            if score > best_score {
+                best_score = score;
+                best_move = Some(mv);
+            }
+            if score > alpha {
+                alpha = score;
+            }
+            if alpha >= beta {
+                // Remember quiet refutations for move ordering
+                let killers = &mut self.killers[ply];
+                if killers[0] != Some(mv) {
+                    killers[1] = killers[0];
+                    killers[0] = Some(mv);
+                }
+                *self.history.entry(mv).or_insert(0) += (depth * depth) as i32;
+                break;
+            }
+        }
+
+        let bound = if best_score <= original_alpha {
+            Bound::Upper
+        } else if best_score >= beta {
+            Bound::Lower
+        } else {
+            Bound::Exact
+        };
+        self.tt[index] = Some(TtEntry { key, depth, score: score_to_tt(best_score, ply), bound, best_move });
+
+        best_score
+    }
+
+    /// Random evaluation offset of a position. It is derived from the
+    /// position hash so it stays the same within a search and the
+    /// transposition table remains consistent.
+    fn noise_for(&self, key: u64) -> i32 {
+        if self.noise <= 0 {
+            return 0;
+        }
+        let mut x = key ^ self.noise_seed;
+        x = (x ^ (x >> 33)).wrapping_mul(0xff51_afd7_ed55_8ccd);
+        x ^= x >> 33;
+        (x % (2 * self.noise as u64 + 1)) as i32 - self.noise
+    }
+
+    /// Sorts moves so the transposition table move comes first, then killer
+    /// moves, then moves with the best history of causing cutoffs.
+    fn order_moves(&self, moves: &mut [Move], tt_move: Option<Move>, ply: usize) {
+        let killers = self.killers[ply];
+        moves.sort_by_cached_key(|mv| {
+            let priority = if Some(*mv) == tt_move {
+                i32::MAX
+            } else if Some(*mv) == killers[0] {
+                i32::MAX - 1
+            } else if Some(*mv) == killers[1] {
+                i32::MAX - 2
+            } else {
+                self.history.get(mv).copied().unwrap_or(0)
+            };
+            -(priority as i64)
+        });
+    }
+
+    /// Follows the best moves stored in the transposition table.
+    fn principal_variation<G: Rules>(&self, game: &mut G, depth: u32) -> Vec<Move> {
+        let mut pv = Vec::new();
+        for _ in 0..depth {
+            let key = game.position_hash();
+            let entry = match self.tt[(key as usize) & (TT_SIZE - 1)] {
+                Some(entry) if entry.key == key => entry,
+                _ => break,
+            };
+            match entry.best_move {
+                Some(mv) if game.play(mv).is_ok() => pv.push(mv),
+                _ => break,
+            }
+        }
+        for _ in 0..pv.len() {
+            let _ = game.undo();
+        }
+        pv
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn forced_results_are_stored_relative_to_the_node() {
+        for ply in [0, 1, 7, MAX_PLY - 1] {
+            for score in [0, 250, -250, WIN_SCORE - 12, -(WIN_SCORE - 12), MATE_BOUND, -MATE_BOUND] {
+                assert_eq!(score_from_tt(score_to_tt(score, ply), ply), score);
+            }
+        }
+        // A win three plies below a node at ply 5 is a win in three from the node
+        assert_eq!(score_to_tt(WIN_SCORE - 8, 5), WIN_SCORE - 3);
+        assert_eq!(score_from_tt(WIN_SCORE - 3, 2), WIN_SCORE - 5);
+        assert_eq!(score_to_tt(-(WIN_SCORE - 8), 5), -(WIN_SCORE - 3));
+    }
+
+    #[test]
+    #[cfg(feature = "brandubh")]
+    fn searches_find_the_shortest_win() {
+        use crate::engine::{Game, Variant};
+
+        let game = Game::from_position(Variant::Brandubh, "7/7/1ak4/7/7/5d1/3a3 a").unwrap();
+        let mut searcher = Searcher::new(Weights::for_variant(Variant::Brandubh));
+        let limits = SearchLimits { max_depth: 4, time_limit: Duration::from_secs(10), eval_noise: 0 };
+        for _ in 0..2 {
+            let result = searcher.search(&game, &limits);
+            assert_eq!(result.best_move, Move::parse("d1-d5", 7).ok());
+            assert_eq!(result.score, WIN_SCORE - 1);
+        }
+    }
+}
@@ -0,0 +1,2123 @@
+// The web server: game pages, moves, live updates and the computer
+// opponents, served with warp.
+
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use serde::Deserialize;
+use tokio::sync::{broadcast, mpsc, oneshot, RwLock};
+
+use uuid::Uuid;
+use warp::{
+    self,
+    Filter,
+    http::{Response, Method, header::SET_COOKIE},
+    reject::Reject,
+    reply::html,
+    // sse::{Event, reply},
+    cors,
+};
+
+use crate::engine::{parse_square, position_string, square_name, Game, Move, Rules, Side, Variant};
+use crate::bot::{BotConfig, EngineKind, MAX_LEVEL, create_bot, hint_bot};
+use crate::archive::{ARCHIVE_FILE, GameRecord, append_record};
+use crate::book::book;
+use crate::analysis::{Analysis, ANALYSIS_TIME_MS, analyze};
+use crate::eval::{Weights, WIN_SCORE};
+use crate::search::{SearchLimits, Searcher};
+use crate::external;
+
+/// Number of engine lines streamed to analysis boards by default, and at most
+const DEFAULT_ENGINE_LINES: usize = 3;
+const MAX_ENGINE_LINES: usize = 5;
+
+/// Thinking time of the first engine update for a position. Each further
+/// update doubles it, up to the maximum, after which the engine waits for
+/// the position to change.
+const ENGINE_LINES_START_MS: u64 = 250;
+const ENGINE_LINES_MAX_MS: u64 = 8000;
+
+/// Games that are waiting or in play with no activity for this long are
+/// abandoned
+const IDLE_GAME_TIMEOUT: Duration = Duration::from_secs(30 * 60);
+
+/// Finished and abandoned games stay available, e.g. for their analysis, for
+/// this long after their last activity before they are removed
+const ENDED_GAME_RETENTION: Duration = Duration::from_secs(60 * 60);
+
+/// How often each game checks whether it has been idle too long
+const EXPIRY_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Requests a game can have queued before senders wait
+const GAME_COMMAND_QUEUE: usize = 64;
+
+use crate::helper_functions::{
+    render_analysis_as_html,
+    render_board_view_as_html,
+    render_game_board_as_html,
+    get_session_id_from_cookie,
+    read_html_template,
+    generate_random_id};
+
+
+#[derive(Clone, Debug)]
+pub enum GameMode {
+    Local,
+    Online,
+    Computer(BotConfig), // Local game against the built-in bot
+}
+
+/// Progress of the computer analysis of a finished game
+#[derive(Clone, Debug)]
+pub enum AnalysisStatus {
+    Running,
+    Done(Arc<Analysis>),
+}
+
+#[derive(Clone)]
+pub struct AppState {
+    pub games: Arc<RwLock<GameRegistry>>,
+    pub players: Arc<RwLock<HashMap<String, (String, String)>>>, // Maps session IDs to usernames
+    pub player_game_map: Arc<RwLock<HashMap<String, usize>>>, // Maps session IDs to game IDs
+    pub analyses: Arc<RwLock<HashMap<usize, AnalysisStatus>>>, // Maps game IDs to their analysis
+    pub archive: mpsc::UnboundedSender<(usize, GameRecord)>, // Finished games, for the archive writer
+}
+
+impl AppState {
+    /// A copy of a game, or None if there is no game with the ID
+    async fn snapshot(&self, id: usize) -> Option<ServerGame> {
+        let handle = self.games.read().await.get(id)?;
+        handle.request(GameCommand::Snapshot).await
+    }
+}
+
+/// A game on the server: the one authoritative position, shared by every
+/// viewer, and what each viewer has selected on it.
+#[derive(Clone, Debug)]
+pub struct ServerGame {
+    pub id: usize,
+    pub game: Game,
+    pub mode: GameMode,
+    pub views: HashMap<String, PlayerView>, // Maps session IDs to their view of the board
+    pub seats: HashMap<String, Side>, // Maps session IDs to the side they play in an online game
+    pub ply: usize, // Moves played so far, the version clients submit moves against
+    pub status: GameStatus,
+    pub last_activity: Instant,
+}
+
+/// Where a game is in its life
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum GameStatus {
+    Waiting,    // An online game waiting for its second player
+    Active,
+    Finished,   // Won by one side and archived
+    Abandoned,  // Left idle before it was over
+}
+
+/// One viewer's projection of a game: the piece they have selected and the
+/// squares it can move to. Selections never touch the shared position.
+#[derive(Clone, Debug, Default)]
+pub struct PlayerView {
+    pub selected: Option<(usize, usize)>,
+    pub targets: Vec<(usize, usize)>,
+}
+
+/// Which pieces a viewer may move
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Perspective {
+    BothSides,      // Local games, played from one browser
+    Side(Side),     // A player of an online game or the human against the computer
+    Spectator,
+}
+
+impl ServerGame {
+    fn new(id: usize, variant: Variant, mode: GameMode) -> Self {
+        ServerGame {
+            id,
+            game: Game::new(variant),
+            views: HashMap::new(),
+            seats: HashMap::new(),
+            ply: 0,
+            status: if matches!(mode, GameMode::Online) { GameStatus::Waiting } else { GameStatus::Active },
+            last_activity: Instant::now(),
+            mode,
+        }
+    }
+
+    fn title(&self) -> &'static str {
+        self.game.variant().title()
+    }
+
+    /// Turn or result shown above the board
+    fn board_message(&self) -> String {
+        match self.game.winner() {
+            Some(winner) => format!("{} wins!", winner.title()),
+            None if self.status == GameStatus::Abandoned => "Game abandoned".to_string(),
+            None => format!("Current turn: {}", self.game.side_to_move().title()),
+        }
+    }
+
+    /// Records activity on the game, which keeps it from expiring.
+    fn touch(&mut self) {
+        self.last_activity = Instant::now();
+    }
+
+    /// Which pieces the viewer with the session ID may move in this game
+    fn perspective(&self, session_id: &str) -> Perspective {
+        match &self.mode {
+            GameMode::Local => Perspective::BothSides,
+            GameMode::Computer(config) => Perspective::Side(config.side.opponent()),
+            GameMode::Online => self.seats.get(session_id).map_or(Perspective::Spectator, |&side| Perspective::Side(side)),
+        }
+    }
+
+    /// Seats a viewer at an online game: at the side they already play, else
+    /// at the preferred side if it is free, else at the free side. Returns
+    /// None once both sides are taken by others.
+    fn take_seat(&mut self, session_id: &str, preferred: Option<Side>) -> Option<Side> {
+        if let Some(&side) = self.seats.get(session_id) {
+            return Some(side);
+        }
+        let taken: Vec<Side> = self.seats.values().copied().collect();
+        let side = preferred
+            .into_iter()
+            .chain([Side::Attacker, Side::Defender])
+            .find(|side| !taken.contains(side))?;
+        self.seats.insert(session_id.to_string(), side);
+
+        // The second player taking their seat starts the game
+        if self.seats.len() == 2 && self.status == GameStatus::Waiting {
+            self.status = GameStatus::Active;
+        }
+        Some(side)
+    }
+
+    /// The board as the viewer with the session ID sees it
+    fn render_for(&self, session_id: &str) -> String {
+        match self.views.get(session_id) {
+            Some(view) => render_board_view_as_html(&self.game, view.selected, &view.targets),
+            None => render_game_board_as_html(&self.game),
+        }
+    }
+
+    /// Applies a click on a square by a viewer: selects one of the pieces of
+    /// the side to move, moves the selected piece to one of its targets, or
+    /// clears the selection. Returns the move if one was played.
+    fn click(&mut self, session_id: &str, perspective: Perspective, pos: (usize, usize)) -> Result<Option<Move>, String> {
+        let size = self.game.size();
+        if pos.0 >= size || pos.1 >= size {
+            return Err("Invalid cell coordinates.".to_string());
+        }
+        self.touch();
+        self.check_can_move(perspective)?;
+        let side = self.game.side_to_move();
+
+        let view = self.views.entry(session_id.to_string()).or_default();
+        if let Some(from) = view.selected {
+            if view.targets.contains(&pos) {
+                let mv = Move::new(from, pos);
+                self.play_move(mv)?;
+                return Ok(Some(mv));
+            }
+        }
+
+        if self.game.piece_at(pos).map(|piece| piece.side()) == Some(side) {
+            view.selected = Some(pos);
+            view.targets = self.game.legal_moves().iter().filter(|mv| mv.from == pos).map(|mv| mv.to).collect();
+        } else {
+            *view = PlayerView::default();
+        }
+        Ok(None)
+    }
+
+    /// Reply to a request made against an earlier position: the conflict and
+    /// the current state, so that the client can catch up.
+    fn conflict(&self, session_id: &str, expected_ply: usize) -> serde_json::Value {
+        serde_json::json!({
+            "success": false,
+            "conflict": true,
+            "error": format!("The game has moved on: expected ply {}, now at ply {}", expected_ply, self.ply),
+            "ply": self.ply,
+            "position": position_string(&self.game),
+            "board_html": self.render_for(session_id),
+            "board_message": self.board_message(),
+        })
+    }
+
+    /// Reply to a click or move that was refused: the reason and the current
+    /// state of the board as the viewer sees it.
+    fn rejection(&self, session_id: &str, error: String) -> serde_json::Value {
+        serde_json::json!({
+            "success": false,
+            "error": error,
+            "ply": self.ply,
+            "board_html": self.render_for(session_id),
+            "board_message": self.board_message(),
+        })
+    }
+
+    /// Checks that a viewer may move the side to move now.
+    fn check_can_move(&self, perspective: Perspective) -> Result<(), String> {
+        if self.game.winner().is_some() {
+            return Err("Game is already over.".to_string());
+        }
+        if self.status == GameStatus::Abandoned {
+            return Err("The game was abandoned".to_string());
+        }
+        match perspective {
+            Perspective::BothSides => Ok(()),
+            Perspective::Side(own) if own == self.game.side_to_move() => Ok(()),
+            Perspective::Side(_) if matches!(self.mode, GameMode::Computer(_)) => Err("The computer is thinking".to_string()),
+            Perspective::Side(_) => Err("Not your turn".to_string()),
+            Perspective::Spectator => Err("Spectators cannot move".to_string()),
+        }
+    }
+
+    /// Plays a complete move submitted by a viewer in one request. Returns
+    /// the squares of the captured pieces.
+    fn submit(&mut self, perspective: Perspective, mv: Move) -> Result<Vec<(usize, usize)>, String> {
+        self.check_can_move(perspective)?;
+        self.play_move(mv)
+    }
+
+    /// Plays a complete move, clearing every viewer's selection, and archives
+    /// the game if the move ended it. Returns the squares of the captured
+    /// pieces.
+    fn play_move(&mut self, mv: Move) -> Result<Vec<(usize, usize)>, String> {
+        let captured = self.game.play(mv)?;
+        self.views.clear();
+        self.ply += 1;
+        self.touch();
+        if self.game.winner().is_some() {
+            self.status = GameStatus::Finished;
+        } else {
+            self.status = GameStatus::Active;
+        }
+        Ok(captured)
+    }
+}
+
+/// The games on the server by ID. Each game runs as its own task, so the
+/// registry only holds the handles to send them requests; its lock is never
+/// held while a game works.
+#[derive(Debug, Default)]
+pub struct GameRegistry {
+    games: HashMap<usize, GameHandle>,
+}
+
+impl GameRegistry {
+    fn get(&self, id: usize) -> Option<GameHandle> {
+        self.games.get(&id).cloned()
+    }
+}
+
+/// Starts a game under a new ID in its own task and returns the ID.
+async fn spawn_game(state: &AppState, variant: Variant, mode: GameMode) -> usize {
+    let mut registry = state.games.write().await;
+    let mut id = generate_random_id();
+    while registry.games.contains_key(&id) {
+        id = generate_random_id();
+    }
+
+    let (commands, receiver) = mpsc::channel(GAME_COMMAND_QUEUE);
+    let actor = GameActor {
+        server_game: ServerGame::new(id, variant, mode),
+        channels: HashMap::new(),
+        commands: commands.downgrade(),
+        state: state.clone(),
+    };
+    registry.games.insert(id, GameHandle { commands });
+    tokio::spawn(actor.run(receiver));
+    id
+}
+
+/// Requests to a game's task. Those with a reply channel are answered once
+/// the request is handled.
+#[derive(Debug)]
+enum GameCommand {
+    /// A copy of the game, e.g. to render a page or to analyse it
+    Snapshot(oneshot::Sender<ServerGame>),
+    /// A viewer opened the game page; counts as activity. The first viewer
+    /// of an online game with a side chosen is its creator and takes that seat.
+    Visit {
+        session_id: Option<String>,
+        side: Option<Side>,
+        reply: oneshot::Sender<ServerGame>,
+    },
+    /// A player followed the link to an online game and takes a free seat
+    Join {
+        session_id: String,
+        reply: oneshot::Sender<JoinOutcome>,
+    },
+    /// Live board updates for a viewer
+    Subscribe {
+        session_id: String,
+        reply: oneshot::Sender<broadcast::Receiver<String>>,
+    },
+    Click {
+        username: String,
+        click: CellClick,
+        reply: oneshot::Sender<serde_json::Value>,
+    },
+    Submit {
+        username: String,
+        submission: MoveSubmission,
+        reply: oneshot::Sender<serde_json::Value>,
+    },
+    /// The computer's move for the position with the given hash
+    ComputerMove { position: u64, mv: Move },
+}
+
+/// Answer of a game to a player joining it
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum JoinOutcome {
+    Seated(Side),
+    Full,       // Both sides are taken; the visitor can watch
+    NotOnline,
+}
+
+/// Sends requests to a game's task
+#[derive(Clone, Debug)]
+struct GameHandle {
+    commands: mpsc::Sender<GameCommand>,
+}
+
+impl GameHandle {
+    /// Sends a request built around a reply channel and waits for the
+    /// answer. None once the game has ended its task.
+    async fn request<T>(&self, command: impl FnOnce(oneshot::Sender<T>) -> GameCommand) -> Option<T> {
+        let (reply, answer) = oneshot::channel();
+        self.commands.send(command(reply)).await.ok()?;
+        answer.await.ok()
+    }
+}
+
+/// The task running one game: it owns the game and the board update
+/// channels of its viewers, and handles the game's requests one at a time.
+struct GameActor {
+    server_game: ServerGame,
+    channels: HashMap<String, broadcast::Sender<String>>, // Maps session IDs to their board updates
+    commands: mpsc::WeakSender<GameCommand>, // For the computer's moves, without keeping the task alive
+    state: AppState,
+}
+
+impl GameActor {
+    /// Handles requests until the game expires, then forgets the game.
+    async fn run(mut self, mut receiver: mpsc::Receiver<GameCommand>) {
+        // The computer opens the game when it plays the attackers
+        self.start_computer_move();
+
+        let mut expiry = tokio::time::interval(EXPIRY_INTERVAL);
+        loop {
+            tokio::select! {
+                command = receiver.recv() => match command {
+                    Some(command) => self.handle(command),
+                    None => break,
+                },
+                _ = expiry.tick() => {
+                    if self.expire(Instant::now()) {
+                        break;
+                    }
+                }
+            }
+        }
+
+        let id = self.server_game.id;
+        self.state.games.write().await.games.remove(&id);
+        self.state.player_game_map.write().await.retain(|_, game_id| *game_id != id);
+        self.state.analyses.write().await.remove(&id);
+        println!("Removed game {}", id);
+    }
+
+    fn handle(&mut self, command: GameCommand) {
+        match command {
+            GameCommand::Snapshot(reply) => {
+                let _ = reply.send(self.server_game.clone());
+            }
+            GameCommand::Visit { session_id, side, reply } => {
+                let server_game = &mut self.server_game;
+                if matches!(server_game.mode, GameMode::Online) && server_game.seats.is_empty() {
+                    if let (Some(session_id), Some(side)) = (session_id, side) {
+                        server_game.take_seat(&session_id, Some(side));
+                    }
+                }
+                server_game.touch();
+                let _ = reply.send(server_game.clone());
+            }
+            GameCommand::Join { session_id, reply } => {
+                let server_game = &mut self.server_game;
+                let outcome = if matches!(server_game.mode, GameMode::Online) {
+                    server_game.touch();
+                    match server_game.take_seat(&session_id, None) {
+                        Some(side) => JoinOutcome::Seated(side),
+                        None => JoinOutcome::Full,
+                    }
+                } else {
+                    JoinOutcome::NotOnline
+                };
+                let _ = reply.send(outcome);
+            }
+            GameCommand::Subscribe { session_id, reply } => {
+                let channel = self.channels.entry(session_id).or_insert_with(|| broadcast::channel(100).0);
+                let _ = reply.send(channel.subscribe());
+            }
+            GameCommand::Click { username, click, reply } => {
+                let _ = reply.send(self.click(&username, &click));
+            }
+            GameCommand::Submit { username, submission, reply } => {
+                let _ = reply.send(self.submit(&username, &submission));
+            }
+            GameCommand::ComputerMove { position, mv } => self.play_computer_move(position, mv),
+        }
+    }
+
+    fn click(&mut self, username: &str, click: &CellClick) -> serde_json::Value {
+        let server_game = &mut self.server_game;
+        let session_id = &click.session_id;
+
+        // Drop clicks made on a board that has changed since
+        if let Some(expected_ply) = click.expected_ply.filter(|&ply| ply != server_game.ply) {
+            return server_game.conflict(session_id, expected_ply);
+        }
+
+        let perspective = server_game.perspective(session_id);
+        let move_made = match server_game.click(session_id, perspective, (click.row, click.col)) {
+            Ok(mv) => mv.is_some(),
+            Err(error) => return server_game.rejection(session_id, error),
+        };
+
+        self.broadcast_board(username);
+        // Hand the turn to the computer once the human has moved
+        if move_made {
+            self.archive_if_finished();
+            self.start_computer_move();
+        }
+
+        let server_game = &self.server_game;
+        serde_json::json!({
+            "success": true,
+            "ply": server_game.ply,
+            "board_html": server_game.render_for(session_id),
+            "board_message": server_game.board_message(),
+        })
+    }
+
+    fn submit(&mut self, username: &str, submission: &MoveSubmission) -> serde_json::Value {
+        let server_game = &mut self.server_game;
+        let session_id = &submission.session_id;
+        if let Some(expected_ply) = submission.expected_ply.filter(|&ply| ply != server_game.ply) {
+            return server_game.conflict(session_id, expected_ply);
+        }
+
+        let size = server_game.game.size();
+        let perspective = server_game.perspective(session_id);
+        let result = submission.to_move(size).and_then(|mv| server_game.submit(perspective, mv).map(|captured| (mv, captured)));
+        let (mv, captured) = match result {
+            Ok(played) => played,
+            Err(error) => return server_game.rejection(session_id, error),
+        };
+        self.broadcast_board(username);
+        self.archive_if_finished();
+        self.start_computer_move();
+
+        let server_game = &self.server_game;
+        let game = &server_game.game;
+        serde_json::json!({
+            "success": true,
+            "move": mv.notation(size),
+            "ply": server_game.ply,
+            "captured": captured.iter().map(|&pos| square_name(pos, size)).collect::<Vec<_>>(),
+            "position": position_string(game),
+            "side_to_move": game.side_to_move().name(),
+            "game_over": game.winner().is_some(),
+            "winner": game.winner().map(|side| side.name()),
+            "board_html": server_game.render_for(session_id),
+            "board_message": server_game.board_message(),
+        })
+    }
+
+    /// Lets the computer move if it is its turn in a game against the bot.
+    /// The search runs on the blocking thread pool and its move comes back
+    /// to this task as a request, so the game keeps answering meanwhile.
+    fn start_computer_move(&self) {
+        let server_game = &self.server_game;
+        let config = match &server_game.mode {
+            GameMode::Computer(config) => config.clone(),
+            _ => return,
+        };
+        let game = server_game.game.clone();
+        if game.winner().is_some() || game.side_to_move() != config.side || server_game.status == GameStatus::Abandoned {
+            return;
+        }
+        let Some(commands) = self.commands.upgrade() else {
+            return;
+        };
+
+        let id = server_game.id;
+        tokio::spawn(async move {
+            let position = game.position_hash();
+            let choice = tokio::task::spawn_blocking(move || {
+                let mut bot = create_bot(&config, game.variant());
+                let mv = bot.choose_move(&game);
+                for message in bot.take_messages() {
+                    println!("Computer in game {}: {}", id, message);
+                }
+                mv
+            });
+            if let Ok(Some(mv)) = choice.await {
+                let _ = commands.send(GameCommand::ComputerMove { position, mv }).await;
+            }
+        });
+    }
+
+    fn play_computer_move(&mut self, position: u64, mv: Move) {
+        // Drop the move if the position changed while the bot was thinking
+        if self.server_game.game.position_hash() != position {
+            return;
+        }
+        match self.server_game.play_move(mv) {
+            Ok(_) => {
+                self.broadcast_board("Computer");
+                self.archive_if_finished();
+            }
+            Err(error) => println!("Computer move failed in game {}: {}", self.server_game.id, error),
+        }
+    }
+
+    /// Hands the game to the archive writer once a move has ended it, so the
+    /// game task never waits on the file.
+    fn archive_if_finished(&self) {
+        let server_game = &self.server_game;
+        if server_game.status != GameStatus::Finished {
+            return;
+        }
+        let computer = match &server_game.mode {
+            GameMode::Computer(config) => Some(config.clone()),
+            _ => None,
+        };
+        let _ = self.state.archive.send((server_game.id, GameRecord::new(&server_game.game, computer)));
+    }
+
+    /// Sends every viewer of the game the board with their own selection.
+    fn broadcast_board(&mut self, username: &str) {
+        let server_game = &self.server_game;
+        let board_message = server_game.board_message();
+        // Forget the viewers who have closed the page
+        self.channels.retain(|_, channel| channel.receiver_count() > 0);
+        for (session_id, channel) in &self.channels {
+            let update = serde_json::json!({
+                "board_html": server_game.render_for(session_id),
+                "board_message": board_message,
+                "ply": server_game.ply,
+                "username": username,
+            });
+            let _ = channel.send(update.to_string());
+        }
+    }
+
+    /// Abandons the game once it has been idle too long, telling its viewers.
+    /// Returns true when the game has ended long enough ago to be removed.
+    fn expire(&mut self, now: Instant) -> bool {
+        let server_game = &mut self.server_game;
+        let idle = now.saturating_duration_since(server_game.last_activity);
+        match server_game.status {
+            GameStatus::Waiting | GameStatus::Active if idle >= IDLE_GAME_TIMEOUT => {
+                server_game.status = GameStatus::Abandoned;
+                server_game.views.clear();
+                server_game.touch();
+                self.broadcast_board("Server");
+                false
+            }
+            GameStatus::Finished | GameStatus::Abandoned => idle >= ENDED_GAME_RETENTION,
+            _ => false,
+        }
+    }
+}
+
+/// Reads the opponent, side, engine, Monte Carlo settings and difficulty
+/// level chosen on the local game page.
+fn local_game_mode(form: &HashMap<String, String>) -> Result<GameMode, String> {
+    if form.get("opponent").map(String::as_str) != Some("computer") {
+        return Ok(GameMode::Local);
+    }
+    let human_side = form.get("side").and_then(|side| Side::from_name(side)).unwrap_or(Side::Attacker);
+    let engine = match form.get("engine").map(String::as_str) {
+        None => EngineKind::AlphaBeta,
+        Some("mcts") => {
+            let settings: Vec<String> = [("playouts", "playouts"), ("c", "exploration")]
+                .iter()
+                .filter_map(|&(key, field)| form.get(field).filter(|value| !value.is_empty()).map(|value| format!("{}={}", key, value.trim())))
+                .collect();
+            EngineKind::parse(&format!("mcts:{}", settings.join(",")))?
+        }
+        Some(spec) => EngineKind::parse(spec)?,
+    };
+    let level = form.get("level").and_then(|level| level.parse().ok()).unwrap_or(MAX_LEVEL);
+    Ok(GameMode::Computer(BotConfig::new(human_side.opponent(), engine, level)))
+}
+
+/// Starts the task that appends finished games to the archive file one at a
+/// time, on the blocking thread pool, and returns its queue.
+fn spawn_archive_writer(path: &'static str) -> mpsc::UnboundedSender<(usize, GameRecord)> {
+    let (sender, mut receiver) = mpsc::unbounded_channel::<(usize, GameRecord)>();
+    tokio::spawn(async move {
+        while let Some((id, record)) = receiver.recv().await {
+            let result = tokio::task::spawn_blocking(move || append_record(path, &record)).await;
+            if let Err(error) = result.unwrap_or_else(|error| Err(error.to_string())) {
+                println!("Could not archive game {}: {}", id, error);
+            }
+        }
+    });
+    sender
+}
+
+/// Analyses a finished game on the blocking thread pool and stores the
+/// result for the analysis page.
+fn start_analysis(state: AppState, game_id: usize, game: Game) {
+    tokio::spawn(async move {
+        let analysis = tokio::task::spawn_blocking(move || {
+            analyze(&game, Weights::for_variant(game.variant()), Duration::from_millis(ANALYSIS_TIME_MS))
+        })
+        .await;
+
+        let mut analyses = state.analyses.write().await;
+        match analysis {
+            Ok(analysis) => {
+                analyses.insert(game_id, AnalysisStatus::Done(Arc::new(analysis)));
+            }
+            Err(error) => {
+                // Forget the failed job so that it can be started again
+                println!("Analysis of game {} failed: {}", game_id, error);
+                analyses.remove(&game_id);
+            }
+        }
+    });
+}
+
+/// Compiled-in variants by board size, largest first, as offered on the
+/// game mode pages
+fn variants_by_size() -> Vec<Variant> {
+    let mut variants = Variant::ALL.to_vec();
+    variants.sort_by_key(|&variant| std::cmp::Reverse(Game::new(variant).size()));
+    variants
+}
+
+/// Stands in for the new game routes of a variant compiled out of this build.
+#[allow(dead_code)]
+fn variant_not_compiled() -> impl Filter<Extract = (Response<&'static str>,), Error = warp::Rejection> + Clone {
+    warp::any().and_then(|| async { Err::<Response<&'static str>, _>(warp::reject::not_found()) })
+}
+
+/// The page of a game. Visitors without a seat at an online game, including
+/// those without a session, watch it.
+async fn game_page(state: &AppState, id: usize, session_id: Option<String>) -> Response<String> {
+    // The side chosen by the creator of an online game, taken on their
+    // first visit
+    let side = match &session_id {
+        Some(session_id) => state.players.read().await.get(session_id).and_then(|(_, role)| Side::from_name(role)),
+        None => None,
+    };
+    let handle = state.games.read().await.get(id);
+    let server_game = match handle {
+        Some(handle) => {
+            let session_id = session_id.clone();
+            handle.request(|reply| GameCommand::Visit { session_id, side, reply }).await
+        }
+        None => None,
+    };
+
+    // If the game is not found, return an error
+    let Some(server_game) = server_game else {
+        return Response::builder()
+            .status(404) // Not Found
+            .body("Game not found.".into())
+            .unwrap();
+    };
+
+    // Remember the game of a seated player
+    if let Some(session_id) = session_id.as_ref().filter(|session_id| server_game.seats.contains_key(*session_id)) {
+        state.player_game_map.write().await.insert(session_id.clone(), id);
+    }
+
+    let viewer = session_id.as_deref().unwrap_or_default();
+    let board_html = server_game.render_for(viewer);
+    let board_message = server_game.board_message();
+    let game_title = server_game.title().to_string();
+    let ply = server_game.ply;
+
+    // The seated players, attacker first, and any computer opponent
+    let mut seats: Vec<(&String, &Side)> = server_game.seats.iter().collect();
+    seats.sort_by_key(|&(_, &side)| side != Side::Attacker);
+    let players = state.players.read().await;
+    let mut players_html: String = seats
+        .into_iter()
+        .filter_map(|(session_id, side)| players.get(session_id).map(|(username, _)| format!("<p>{} ({})</p>", username, side.name())))
+        .collect();
+    drop(players);
+    if let GameMode::Computer(config) = &server_game.mode {
+        match &config.engine {
+            EngineKind::External { name } => players_html.push_str(&format!("<p>Computer ({}, {})</p>", config.side, name)),
+            _ => players_html.push_str(&format!("<p>Computer ({}, level {})</p>", config.side, config.level)),
+        }
+    }
+
+    // Only local games offer move hints
+    let hint_html = match server_game.mode {
+        GameMode::Online => "",
+        _ => r#"<button onclick="requestHint()">Hint</button><p id="hint-message"></p>"#,
+    };
+
+    // Engine lines for analysis boards and spectators
+    let engine_lines_html = if engine_lines_allowed(&server_game, session_id.as_deref()) {
+        r#"<button onclick="toggleEngineLines()">Engine analysis</button>
+            <div id="engine-panel" style="display: none;">
+                <div id="eval-bar"><div id="eval-bar-fill"></div></div>
+                <p id="engine-eval"></p>
+                <ol id="engine-lines"></ol>
+            </div>"#
+    } else {
+        ""
+    };
+
+    let template_path = "templates/game.html";
+    let template = read_html_template(template_path).unwrap();
+
+    // Embed the session_id in a <script> tag in the response
+    let session_script = if let Some(session_id) = session_id {
+        format!(r#"<script>const session_id = "{}";</script>"#, session_id)
+    } else {
+        "<script>const session_id = null;</script>".to_string()
+    };
+
+    let response = template
+        .replace("{game_title}", &game_title)
+        .replace("{board_message}", &board_message)
+        .replace("{board_html}", &board_html)
+        .replace("{ply}", &ply.to_string())
+        .replace("{id}", &id.to_string())
+        .replace("{player_username}", "")
+        .replace("{players_html}", &players_html)
+        .replace("{hint_html}", hint_html)
+        .replace("{engine_lines_html}", engine_lines_html)
+        .replace("</head>", &format!("{}\n</head>", session_script)); // Add session script to the head
+
+    Response::builder().body(response).unwrap()
+}
+
+/// Whether a visitor may follow the engine's evaluation of a game. It is
+/// hidden from the players seated at an online game until the game is over.
+/// Players are only known by their session cookie, so this keeps the lines
+/// out of a player's own page but cannot stop a player who visits without
+/// the cookie, e.g. from a private window, from watching as a spectator.
+fn engine_lines_allowed(server_game: &ServerGame, session_id: Option<&str>) -> bool {
+    match server_game.mode {
+        GameMode::Online => {
+            let is_player = session_id.is_some_and(|session_id| server_game.seats.contains_key(session_id));
+            !is_player || server_game.game.winner().is_some()
+        }
+        GameMode::Local | GameMode::Computer(_) => true,
+    }
+}
+
+/// Keeps evaluating the current position of a game and yields the best
+/// `count` lines as JSON after every search, thinking longer each time the
+/// position is unchanged.
+fn engine_line_updates(state: AppState, game_id: usize, count: usize) -> impl futures_util::Stream<Item = Result<warp::sse::Event, warp::Error>> {
+    async_stream::stream! {
+        let mut searcher = None;
+        let mut last_position = None;
+        let mut think_time = ENGINE_LINES_START_MS;
+
+        loop {
+            let game = match state.snapshot(game_id).await {
+                Some(server_game) => server_game.game,
+                None => break,
+            };
+
+            let position = game.position_hash();
+            if last_position != Some(position) {
+                last_position = Some(position);
+                think_time = ENGINE_LINES_START_MS;
+            } else if think_time >= ENGINE_LINES_MAX_MS || game.winner().is_some() {
+                tokio::time::sleep(Duration::from_millis(500)).await;
+                continue;
+            } else {
+                think_time = (think_time * 2).min(ENGINE_LINES_MAX_MS);
+            }
+
+            let side = game.side_to_move();
+            let size = game.size();
+            let for_attacker = |score: i32| if side == Side::Attacker { score } else { -score };
+            let update = match game.winner() {
+                Some(winner) => serde_json::json!({
+                    "eval": if winner == Side::Attacker { WIN_SCORE } else { -WIN_SCORE },
+                    "depth": 0,
+                    "lines": [],
+                }),
+                None => {
+                    let limits = SearchLimits {
+                        max_depth: 32,
+                        time_limit: Duration::from_millis(think_time),
+                        eval_noise: 0,
+                    };
+                    let mut engine = searcher.take().unwrap_or_else(|| Searcher::new(Weights::for_variant(game.variant())));
+                    let lines = match tokio::task::spawn_blocking(move || {
+                        let lines = engine.search_lines(&game, &limits, count);
+                        (engine, lines)
+                    })
+                    .await
+                    {
+                        Ok((engine, lines)) => {
+                            searcher = Some(engine);
+                            lines
+                        }
+                        Err(_) => break,
+                    };
+                    serde_json::json!({
+                        "eval": lines.first().map_or(0, |line| for_attacker(line.score)),
+                        "depth": lines.first().map_or(0, |line| line.depth),
+                        "lines": lines.iter().map(|line| serde_json::json!({
+                            "score": for_attacker(line.score),
+                            "moves": line.pv.iter().map(|mv| mv.notation(size)).collect::<Vec<_>>().join(" "),
+                        })).collect::<Vec<_>>(),
+                    })
+                }
+            };
+            yield Ok(warp::sse::Event::default().data(update.to_string()));
+        }
+    }
+}
+
+
+#[derive(Debug, Deserialize)]
+struct CellClick {
+    row: usize,
+    col: usize,
+    session_id: String,
+    expected_ply: Option<usize>, // Ply of the board the click was made on
+}
+
+
+/// A complete move submitted in one request, either as two squares or in
+/// notation, e.g. "d1-d3"
+#[derive(Debug, Deserialize)]
+struct MoveSubmission {
+    session_id: String,
+    expected_ply: Option<usize>, // Ply of the position the move was chosen in
+    from: Option<SquareSpec>,
+    to: Option<SquareSpec>,
+    notation: Option<String>,
+}
+
+/// A square given by its coordinates or by its name, e.g. "d1"
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+enum SquareSpec {
+    Coordinates { row: usize, col: usize },
+    Name(String),
+}
+
+impl SquareSpec {
+    fn resolve(&self, size: usize) -> Result<(usize, usize), String> {
+        match self {
+            SquareSpec::Coordinates { row, col } if *row < size && *col < size => Ok((*row, *col)),
+            SquareSpec::Coordinates { .. } => Err("Invalid cell coordinates.".to_string()),
+            SquareSpec::Name(name) => parse_square(name, size),
+        }
+    }
+}
+
+impl MoveSubmission {
+    fn to_move(&self, size: usize) -> Result<Move, String> {
+        match (&self.notation, &self.from, &self.to) {
+            (Some(notation), None, None) => Move::parse(notation, size),
+            (None, Some(from), Some(to)) => Ok(Move::new(from.resolve(size)?, to.resolve(size)?)),
+            _ => Err("Give either from and to, or the move in notation".to_string()),
+        }
+    }
+}
+
+
+#[derive(Debug)]
+struct MissingUsername;
+
+impl fmt::Display for MissingUsername {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Missing username")
+    }
+}
+
+impl Reject for MissingUsername {}
+
+#[derive(Deserialize)]
+struct FormData {
+    game_mode: String,
+    side: String,
+}
+
+/// Runs the web server on port 3030 until the process is stopped.
+#[tokio::main]
+pub async fn run() {
+    // Static file serving for images
+    let static_files = warp::path("images").and(warp::fs::dir("./static/images"));
+
+    // Initialize application state
+    let state = AppState {
+        games: Arc::new(RwLock::new(GameRegistry::default())),
+        players: Arc::new(RwLock::new(HashMap::new())),
+        player_game_map: Arc::new(RwLock::new(HashMap::new())),
+        analyses: Arc::new(RwLock::new(HashMap::new())),
+        archive: spawn_archive_writer(ARCHIVE_FILE),
+    };
+
+    let state_filter = {
+        let state = state.clone();
+        warp::any().map(move || state.clone())
+    };
+
+    // Root route to show the username form
+    let username_form = warp::path::end()
+        .and(warp::get())
+        .map(|| {
+            let html_content = read_html_template("templates/username_form.html").unwrap();
+
+            // Return the HTML as a response
+            html(html_content)
+        });
+
+    // Handle POST request for username submission and show main page
+    let main_page_post = warp::path("main")
+        .and(warp::post()) // POST method
+        .and(warp::body::form()) // To receive form data
+        .and(state_filter.clone()) // Access app state
+        .and(warp::header::headers_cloned()) // Get the headers (to check cookies)
+        .and_then(|form: HashMap<String, String>, state: AppState, headers: warp::http::HeaderMap| async move {
+            // Check if the session_id exists in the cookies
+            if let Some(session_id) = get_session_id_from_cookie(&headers) {
+                // If session_id exists, use the existing username
+                let players = state.players.read().await;
+                if let Some((username, _)) = players.get(&session_id) {
+                    // Session already exists, don't ask for the username again
+                    let players_html: String = players
+                        .values()
+                        .map(|(username, _)| format!("<p>{}</p>", username))
+                        .collect();
+
+                    // Read the HTML template from file
+                    let template_path = "templates/main_page.html";
+                    let template = read_html_template(template_path).unwrap();
+
+                    // Replace placeholders in the template with dynamic content
+                    let response = template
+                        .replace("{welcome_message}", &format!("Welcome to the Hnefatafl server, {}!", username))
+                        .replace("{players_html}", &players_html)
+                        .replace("{session_id}", &session_id);
+
+                    return Ok::<_, warp::Rejection>(Response::builder().body(response).unwrap());
+                }
+            }
+
+            // If no session exists, proceed with creating a new session
+            if let Some(username) = form.get("username") {
+                let session_id = Uuid::new_v4().to_string(); // Generate a unique session ID
+                state.players.write().await.insert(session_id.clone(), (username.clone(), "local".to_string()));
+
+                // Set session_id in a cookie
+                let cookie = format!("session_id={}; Path=/; HttpOnly;", session_id);
+
+                // Now show the main page with the list of players
+                let players = state.players.read().await; // Read the list of connected players
+
+                // Build the players list in HTML
+                let players_html: String = players
+                    .values()
+                    .map(|(username, _)| format!("<p>{}</p>", username))
+                    .collect();
+
+                // Read the HTML template from file
+                let template_path = "templates/main_page.html";
+                let template = read_html_template(template_path).unwrap();
+
+                // Replace placeholders in the template with dynamic content
+                let response = template
+                    .replace("{welcome_message}", &format!("Welcome to the Hnefatafl server, {}!", username))
+                    .replace("{players_html}", &players_html)
+                    .replace("{session_id}", &session_id);
+
+                return Ok::<_, warp::Rejection>(Response::builder()
+                    .header(SET_COOKIE, cookie)
+                    .body(response)
+                    .unwrap());
+            }
+
+            // If no username, reject the request
+            Err(warp::reject::custom(MissingUsername))
+        });
+
+    // Handle GET request for the main page (similarly as in the previous example)
+    let main_page_get = warp::path("main")
+        .and(warp::get()) // GET method
+        .and(state_filter.clone()) // Access app state
+        .and(warp::header::headers_cloned()) // Get the headers (to check cookies)
+        .and_then(|state: AppState, headers: warp::http::HeaderMap| async move {
+            if let Some(session_id) = get_session_id_from_cookie(&headers) {
+                // Check if the session_id exists in the players map
+                let players = state.players.read().await;
+                if let Some((username, _)) = players.get(&session_id) {
+                    // Build the players list in HTML
+                    let players_html: String = players
+                        .values()
+                        .map(|(username, _)| format!("<p>{}</p>", username))
+                        .collect();
+
+                    // Read the HTML template from file
+                    let template_path = "templates/main_page.html";
+                    let template = read_html_template(template_path).unwrap();
+
+                    // Replace placeholders in the template with dynamic content
+                    let response = template
+                        .replace("{welcome_message}", &format!("Welcome to the Hnefatafl server, {}!", username))
+                        .replace("{players_html}", &players_html)
+                        .replace("{session_id}", &session_id);
+
+                    return Ok::<_, warp::Rejection>(Response::builder().body(response).unwrap());
+                }
+            }
+
+            // If no session exists, redirect to login page (you can return a 404 or redirect)
+            Ok::<_, warp::Rejection>(Response::builder().status(404).body("Not Found".to_string()).unwrap())
+        });
+
+    // Handle POST request for signing out
+    let sign_out_post = warp::path("signout")
+        .and(warp::post()) // POST method
+        .and(warp::body::form()) // To receive form data
+        .and(state_filter.clone())
+        .and_then(|form: HashMap<String, String>, state: AppState| async move {
+            let session_id = form.get("session_id").expect("Session ID must be present");
+
+            let mut players = state.players.write().await;
+            players.remove(session_id); // Remove the player from the list
+
+            // Redirect to the username input page
+            let response = warp::http::Response::builder()
+                .status(302)
+                .header("Location", "/")
+                .body("Redirecting...")
+                .unwrap();
+
+            Ok::<_, warp::Rejection>(response)
+        });
+
+
+    // Endpoint: Display the rules
+    let rules = warp::path("rules")
+        .and(warp::get())
+        .map(move || {
+            // Read the HTML template from file (assuming the file exists)
+            let template_path = "templates/rules.html";
+            let template = read_html_template(template_path).unwrap(); // We assume the file exists and unwrap the result
+
+            // Return the template as a valid HTML response
+            warp::reply::html(template)
+        });
+
+    let game_mode_local = warp::path("game_mode_local")
+        .and(warp::post())
+        .map(move || {
+            // Read the HTML template from file (assuming the file exists)
+            let template_path = "templates/game_mode_local.html";
+            let template = read_html_template(template_path).unwrap(); // We assume the file exists and unwrap the result
+            let external_engines: String = external::engine_names()
+                .iter()
+                .map(|name| format!(r#"<option value="external:{0}">{0} (external)</option>"#, name))
+                .collect();
+            let variant_buttons: String = variants_by_size()
+                .iter()
+                .map(|&variant| {
+                    format!(
+                        r#"<button type="submit" formaction="/{}_redirect_local">{} ({2}x{2})</button>"#,
+                        variant,
+                        variant.title(),
+                        Game::new(variant).size()
+                    )
+                })
+                .collect();
+            let template = template
+                .replace("{external_engines}", &external_engines)
+                .replace("{variant_buttons}", &variant_buttons);
+
+            // Return the template as a valid HTML response
+            html(template)
+        });
+
+    let game_mode_online = warp::path("game_mode_online")
+        .and(warp::post())
+        .map(move || {
+            // Read the HTML template from file (assuming the file exists)
+            let template_path = "templates/game_mode_online.html";
+            let template = read_html_template(template_path).unwrap(); // We assume the file exists and unwrap the result
+            let variant_options: String = variants_by_size()
+                .iter()
+                .enumerate()
+                .map(|(index, &variant)| {
+                    format!(
+                        r#"<label><input type="radio" name="game_mode" value="{}"{}> {} ({3}x{3})</label>"#,
+                        variant,
+                        if index == 0 { " required" } else { "" },
+                        variant.title(),
+                        Game::new(variant).size()
+                    )
+                })
+                .collect();
+            let template = template.replace("{variant_options}", &variant_options);
+
+            // Return the template as a valid HTML response
+            html(template)
+        });
+
+    // Redirect endpoint
+    let redirect_endpoint = warp::path("redirect_endpoint")
+        .and(warp::post())
+        .and(state_filter.clone())
+        .and(warp::body::form()) // Parse form data
+        .and(warp::cookie::optional("session_id")) // Retrieve session_id from cookies
+        .and_then(|state: AppState, form: FormData, session_id: Option<String>| async move {
+            if let Some(session_id) = session_id {
+                let mut players = state.players.write().await; // Acquire write lock
+
+                if let Some(player_data) = players.get_mut(&session_id) {
+                    // Update the role field
+                    player_data.1 = form.side.clone();
+                } else {
+                    // Session ID not found in players map
+                    let response = warp::http::Response::builder()
+                        .status(400)
+                        .body("Session ID not found".to_string())
+                        .unwrap();
+                    return Ok::<warp::http::Response<String>, warp::Rejection>(response);
+                }
+            } else {
+                // No session ID provided
+                let response = warp::http::Response::builder()
+                    .status(400)
+                    .body("Missing session ID".to_string())
+                    .unwrap();
+                return Ok::<warp::http::Response<String>, warp::Rejection>(response);
+            }
+
+            // Determine redirect URL
+            let redirect_url = match (Variant::from_name(&form.game_mode), form.side.as_str()) {
+                (Some(variant), "attacker" | "defender") => format!("/{}_redirect_online", variant),
+                _ => return Ok::<_, warp::Rejection>(
+                    warp::http::Response::builder()
+                        .status(400)
+                        .body("Invalid game mode or role".into())
+                        .unwrap(),
+                ),
+            };
+
+            // Redirect response
+            let response = warp::http::Response::builder()
+                .status(302)
+                .header("Location", redirect_url)
+                .body("Redirecting...".into())
+                .unwrap();
+
+            Ok::<_, warp::Rejection>(response)
+        });
+
+
+
+    // Endpoint: Create a new hnefataflgame and redirect to it
+    #[cfg(feature = "hnefatafl")]
+    let hnefatafl_redirect_local = warp::path("hnefatafl_redirect_local")
+        .and(warp::post())
+        .and(state_filter.clone())
+        .and(warp::body::form())
+        .and_then(|state: AppState, form: HashMap<String, String>| async move {
+            let mode = match local_game_mode(&form) {
+                Ok(mode) => mode,
+                Err(error) => return Ok::<_, warp::Rejection>(Response::builder().status(400).body(error).unwrap()),
+            };
+            let id = spawn_game(&state, Variant::Hnefatafl, mode).await;
+
+            // Redirect to the new game page
+            let response = warp::http::Response::builder()
+                .status(302)
+                .header("Location", format!("/game/{}", id))
+                .body("Redirecting to new game...".to_string())
+                .unwrap();
+
+            Ok::<_, warp::Rejection>(response)
+        });
+
+    #[cfg(feature = "hnefatafl")]
+    let hnefatafl_redirect_online = warp::path("hnefatafl_redirect_online")
+        .and(warp::get())
+        .and(state_filter.clone())
+        .and_then(|state: AppState| async move {
+            let id = spawn_game(&state, Variant::Hnefatafl, GameMode::Online).await;
+
+            // Redirect to the new game page
+            let response = warp::http::Response::builder()
+                .status(302)
+                .header("Location", format!("/game/{}", id))
+                .body("Redirecting to new game...")
+                .unwrap();
+
+            Ok::<_, warp::Rejection>(response)
+        });
+
+    // Endpoint: Create a new tablut game and redirect to it
+    #[cfg(feature = "tablut")]
+    let tablut_redirect_local = warp::path("tablut_redirect_local")
+        .and(warp::post())
+        .and(state_filter.clone())
+        .and(warp::body::form())
+        .and_then(|state: AppState, form: HashMap<String, String>| async move {
+            let mode = match local_game_mode(&form) {
+                Ok(mode) => mode,
+                Err(error) => return Ok::<_, warp::Rejection>(Response::builder().status(400).body(error).unwrap()),
+            };
+            let id = spawn_game(&state, Variant::Tablut, mode).await;
+
+            // Redirect to the new game page
+            let response = warp::http::Response::builder()
+                .status(302)
+                .header("Location", format!("/game/{}", id))
+                .body("Redirecting to new game...".to_string())
+                .unwrap();
+
+            Ok::<_, warp::Rejection>(response)
+        });
+
+    #[cfg(feature = "tablut")]
+    let tablut_redirect_online = warp::path("tablut_redirect_online")
+        .and(warp::get())
+        .and(state_filter.clone())
+        .and_then(|state: AppState| async move {
+            let id = spawn_game(&state, Variant::Tablut, GameMode::Online).await;
+
+            // Redirect to the new game page
+            let response = warp::http::Response::builder()
+                .status(302)
+                .header("Location", format!("/game/{}", id))
+                .body("Redirecting to new game...")
+                .unwrap();
+
+            Ok::<_, warp::Rejection>(response)
+        });
+
+    // Endpoint: Create a new brandubh game and redirect to it
+    #[cfg(feature = "brandubh")]
+    let brandubh_redirect_local = warp::path("brandubh_redirect_local")
+        .and(warp::post())
+        .and(state_filter.clone())
+        .and(warp::body::form())
+        .and_then(|state: AppState, form: HashMap<String, String>| async move {
+            let mode = match local_game_mode(&form) {
+                Ok(mode) => mode,
+                Err(error) => return Ok::<_, warp::Rejection>(Response::builder().status(400).body(error).unwrap()),
+            };
+            let id = spawn_game(&state, Variant::Brandubh, mode).await;
+
+            // Redirect to the new game page
+            let response = warp::http::Response::builder()
+                .status(302)
+                .header("Location", format!("/game/{}", id))
+                .body("Redirecting to new game...".to_string())
+                .unwrap();
+
+            Ok::<_, warp::Rejection>(response)
+        });
+
+    #[cfg(feature = "brandubh")]
+    let brandubh_redirect_online = warp::path("brandubh_redirect_online")
+        .and(warp::get())
+        .and(state_filter.clone())
+        .and_then(|state: AppState| async move {
+            let id = spawn_game(&state, Variant::Brandubh, GameMode::Online).await;
+
+            // Redirect to the new game page
+            let response = warp::http::Response::builder()
+                .status(302)
+                .header("Location", format!("/game/{}", id))
+                .body("Redirecting to new game...")
+                .unwrap();
+
+            Ok::<_, warp::Rejection>(response)
+        });
+
+    // Endpoint: Create a new brandubh game and redirect to it
+    #[cfg(feature = "koch")]
+    let koch_redirect_local = warp::path("koch_redirect_local")
+        .and(warp::post())
+        .and(state_filter.clone())
+        .and(warp::body::form())
+        .and_then(|state: AppState, form: HashMap<String, String>| async move {
+            let mode = match local_game_mode(&form) {
+                Ok(mode) => mode,
+                Err(error) => return Ok::<_, warp::Rejection>(Response::builder().status(400).body(error).unwrap()),
+            };
+            let id = spawn_game(&state, Variant::Koch, mode).await;
+
+            // Redirect to the new game page
+            let response = warp::http::Response::builder()
+                .status(302)
+                .header("Location", format!("/game/{}", id))
+                .body("Redirecting to new game...".to_string())
+                .unwrap();
+
+            Ok::<_, warp::Rejection>(response)
+        });
+
+    #[cfg(feature = "koch")]
+    let koch_redirect_online = warp::path("koch_redirect_online")
+        .and(warp::get())
+        .and(state_filter.clone())
+        .and_then(|state: AppState| async move {
+            let id = spawn_game(&state, Variant::Koch, GameMode::Online).await;
+
+            // Redirect to the new game page
+            let response = warp::http::Response::builder()
+                .status(302)
+                .header("Location", format!("/game/{}", id))
+                .body("Redirecting to new game...")
+                .unwrap();
+
+            Ok::<_, warp::Rejection>(response)
+        });
+
+    // Variants compiled out of this build have no new game routes
+    #[cfg(not(feature = "tablut"))]
+    let (tablut_redirect_local, tablut_redirect_online) = (variant_not_compiled(), variant_not_compiled());
+    #[cfg(not(feature = "brandubh"))]
+    let (brandubh_redirect_local, brandubh_redirect_online) = (variant_not_compiled(), variant_not_compiled());
+    #[cfg(not(feature = "hnefatafl"))]
+    let (hnefatafl_redirect_local, hnefatafl_redirect_online) = (variant_not_compiled(), variant_not_compiled());
+    #[cfg(not(feature = "koch"))]
+    let (koch_redirect_local, koch_redirect_online) = (variant_not_compiled(), variant_not_compiled());
+
+
+    // Endpoint to create a new game and its broadcast channel
+    let new_game = warp::path!("game" / usize)
+        .and(warp::get())
+        .and(state_filter.clone())
+        .and(warp::cookie::optional("session_id")) // Retrieve session_id from cookies
+        .and_then(|id: usize, state: AppState, session_id: Option<String>| async move {
+            Ok::<_, warp::Rejection>(game_page(&state, id, session_id).await)
+        });
+
+
+    // Endpoint: Join a game by ID
+    let join_game_by_id = warp::path("join")
+        .and(warp::post())
+        .map( || {
+            // Read the HTML template from file
+            let template_path = "templates/join_game.html";
+            let template = read_html_template(template_path);
+            // If no matching game is found, return the join game page
+            html(template.unwrap())
+        });
+
+    // Endpoint: Redirect to a game by ID
+    let redirect_to_game = warp::path!("redirect" / usize)
+        .and(state_filter.clone())
+        .and(warp::header::headers_cloned()) 
+        .and_then(|game_id: usize, state: AppState, headers: warp::http::HeaderMap| async move {
+            let player_id: String;
+            if let Some(session_id) = get_session_id_from_cookie(&headers) {
+                player_id = session_id;
+            } else {
+                return Ok::<_, warp::Rejection>(
+                    warp::http::Response::builder()
+                        .status(400)
+                        .body("Missing session ID".to_string())
+                        .unwrap(),
+                );
+            }            
+
+            // Take a free seat at the game, if it is an online one
+            let handle = state.games.read().await.get(game_id);
+            let outcome = match handle {
+                Some(handle) => handle.request(|reply| GameCommand::Join { session_id: player_id.clone(), reply }).await,
+                None => None,
+            };
+
+            if matches!(outcome, Some(JoinOutcome::Seated(_) | JoinOutcome::Full)) {
+                // Play the side of the seat taken; a full game is watched
+                if let Some(JoinOutcome::Seated(side)) = outcome {
+                    if let Some((_username, role)) = state.players.write().await.get_mut(&player_id) {
+                        *role = side.name().to_string();
+                    }
+                }
+
+                // Redirect to the game
+                let response = warp::http::Response::builder()
+                    .status(302)
+                    .header("Location", format!("/game/{}", game_id))
+                    .body("Redirecting to game...".to_string())
+                    .unwrap();
+                Ok::<_, warp::Rejection>(response)
+            } else {
+                // Return error message if the game is not online or doesn't exist
+                let error_response = warp::http::Response::builder()
+                    .status(400) // Bad Request
+                    .body("Cannot connect to game. Either the game does not exist or is not online.".to_string())
+                    .unwrap();
+                Ok::<_, warp::Rejection>(error_response)
+            }
+        });
+
+
+
+    // Endpoint: Suggest a move for the side to move (local games only)
+    let hint = warp::path!("hint" / usize)
+        .and(warp::get())
+        .and(state_filter.clone())
+        .and_then(|game_id: usize, state: AppState| async move {
+            let game = match state.snapshot(game_id).await {
+                Some(server_game) => match &server_game.mode {
+                    GameMode::Online => Err("Hints are not available in online games"),
+                    GameMode::Computer(config) if server_game.game.side_to_move() == config.side => {
+                        Err("The computer is thinking")
+                    }
+                    _ => Ok(server_game.game),
+                },
+                None => Err("Game not found"),
+            };
+            let game = match game {
+                Ok(game) if game.winner().is_some() => Err("The game is over"),
+                other => other,
+            };
+            let game = match game {
+                Ok(game) => game,
+                Err(error) => return Ok::<_, warp::Rejection>(warp::reply::json(&serde_json::json!({
+                    "success": false,
+                    "error": error,
+                }))),
+            };
+
+            let size = game.size();
+            let suggestion = tokio::task::spawn_blocking(move || hint_bot(game.variant()).choose_move(&game))
+                .await
+                .ok()
+                .flatten();
+            Ok(warp::reply::json(&match suggestion {
+                Some(mv) => serde_json::json!({
+                    "success": true,
+                    "move": mv.notation(size),
+                    "from": { "row": mv.from.0, "col": mv.from.1 },
+                    "to": { "row": mv.to.0, "col": mv.to.1 },
+                }),
+                None => serde_json::json!({
+                    "success": false,
+                    "error": "No legal move",
+                }),
+            }))
+        });
+
+
+    // Endpoint: Browse the opening book, following a line of comma-separated moves
+    let book_page = warp::path!("book" / String)
+        .and(warp::get())
+        .and(warp::query::<HashMap<String, String>>())
+        .and_then(|variant_name: String, query: HashMap<String, String>| async move {
+            let variant = match Variant::from_name(&variant_name) {
+                Some(variant) => variant,
+                None => return Err(warp::reject::not_found()),
+            };
+
+            // Replay the line, stopping at the first move that cannot be played
+            let mut game = Game::new(variant);
+            let mut line: Vec<String> = Vec::new();
+            for notation in query.get("moves").map(String::as_str).unwrap_or("").split(',').filter(|text| !text.is_empty()) {
+                match Move::parse(notation, game.size()).and_then(|mv| game.play(mv)) {
+                    Ok(_) => line.push(notation.to_string()),
+                    Err(_) => break,
+                }
+            }
+
+            let size = game.size();
+            let side = game.side_to_move();
+            let book_moves = book().map(|book| book.moves(variant, game.position_hash())).unwrap_or_default();
+            let mut moves_html = String::new();
+            if book_moves.is_empty() {
+                moves_html.push_str("<p>No book moves from this position.</p>");
+            } else {
+                moves_html.push_str("<table><tr><th>Move</th><th>Games</th><th>Attacker wins</th><th>Defender wins</th></tr>");
+                for entry in &book_moves {
+                    let notation = entry.mv.notation(size);
+                    let mut next_line = line.clone();
+                    next_line.push(notation.clone());
+                    moves_html.push_str(&format!(
+                        r#"<tr><td><a href="/book/{}?moves={}">{}</a></td><td>{}</td><td>{:.0}%</td><td>{:.0}%</td></tr>"#,
+                        variant,
+                        next_line.join(","),
+                        notation,
+                        entry.games,
+                        entry.win_rate(Side::Attacker) * 100.0,
+                        entry.win_rate(Side::Defender) * 100.0
+                    ));
+                }
+                moves_html.push_str("</table>");
+            }
+
+            let line_html = if line.is_empty() {
+                "Start position".to_string()
+            } else {
+                let back = line[..line.len() - 1].join(",");
+                format!(r#"{} <a href="/book/{}?moves={}">(back)</a>"#, line.join(" "), variant, back)
+            };
+
+            let mut variants = Variant::ALL.to_vec();
+            variants.sort_by_key(|&variant| Game::new(variant).size());
+            let variant_links: Vec<String> = variants
+                .iter()
+                .map(|&variant| format!(r#"<a href="/book/{}">{}</a>"#, variant, variant.title()))
+                .collect();
+
+            let template = read_html_template("templates/book.html").unwrap();
+            let response = template
+                .replace("{variant_links}", &variant_links.join(" |\n        "))
+                .replace("{variant}", variant.name())
+                .replace("{line_html}", &line_html)
+                .replace("{side}", side.name())
+                .replace("{board_html}", &render_game_board_as_html(&game))
+                .replace("{moves_html}", &moves_html);
+
+            Ok::<_, warp::Rejection>(Response::builder().body(response).unwrap())
+        });
+
+
+    // Endpoint: Computer analysis of a finished game, started on the first visit
+    let analysis_page = warp::path!("analysis" / usize)
+        .and(warp::get())
+        .and(state_filter.clone())
+        .and_then(|game_id: usize, state: AppState| async move {
+            let game = state.snapshot(game_id).await.map(|server_game| (server_game.title(), server_game.game));
+
+            let mut game_title = String::new();
+            let mut running = false;
+            let analysis_html = match game {
+                None => "<p>Game not found.</p>".to_string(),
+                Some((title, game)) => {
+                    game_title = title.to_string();
+                    if game.winner().is_none() {
+                        "<p>The analysis is available once the game is over.</p>".to_string()
+                    } else {
+                        let mut analyses = state.analyses.write().await;
+                        match analyses.get(&game_id) {
+                            Some(AnalysisStatus::Done(analysis)) => render_analysis_as_html(analysis),
+                            Some(AnalysisStatus::Running) => {
+                                running = true;
+                                "<p>The analysis is running, this page refreshes until it is done.</p>".to_string()
+                            }
+                            None => {
+                                analyses.insert(game_id, AnalysisStatus::Running);
+                                start_analysis(state.clone(), game_id, game);
+                                running = true;
+                                "<p>The analysis has started, this page refreshes until it is done.</p>".to_string()
+                            }
+                        }
+                    }
+                }
+            };
+
+            let template = read_html_template("templates/analysis.html").unwrap();
+            let response = template
+                .replace("{refresh}", if running { r#"<meta http-equiv="refresh" content="3">"# } else { "" })
+                .replace("{game_title}", &game_title)
+                .replace("{id}", &game_id.to_string())
+                .replace("{analysis_html}", &analysis_html);
+
+            Ok::<_, warp::Rejection>(Response::builder().body(response).unwrap())
+        });
+
+
+    // Endpoint: Stream the engine's best lines for the current position
+    let engine_lines = warp::path!("engine-lines" / usize)
+        .and(warp::get())
+        .and(warp::query::<HashMap<String, String>>())
+        .and(state_filter.clone())
+        .and(warp::cookie::optional("session_id"))
+        .and_then(|game_id: usize, query: HashMap<String, String>, state: AppState, session_id: Option<String>| async move {
+            let server_game = state.snapshot(game_id).await;
+            let allowed = server_game.is_some_and(|server_game| engine_lines_allowed(&server_game, session_id.as_deref()));
+            if !allowed {
+                return Err(warp::reject::not_found());
+            }
+
+            let count = query
+                .get("lines")
+                .and_then(|lines| lines.parse().ok())
+                .unwrap_or(DEFAULT_ENGINE_LINES)
+                .clamp(1, MAX_ENGINE_LINES);
+            Ok::<_, warp::Rejection>(warp::sse::reply(
+                warp::sse::keep_alive().stream(engine_line_updates(state.clone(), game_id, count)),
+            ))
+        });
+
+
+    // Endpoint for board updates
+    let board_updates = warp::path!("board-updates" / usize)
+        .and(warp::get())
+        .and(state_filter.clone())
+        .and(warp::cookie::optional("session_id")) // Capture the session ID from cookies
+        .and_then(
+            |id: usize, state: AppState, session_id: Option<String>| async move {
+                let Some(session_id) = session_id else {
+                    return Err(warp::reject::not_found());
+                };
+                let handle = state.games.read().await.get(id).ok_or_else(warp::reject::not_found)?;
+                let rx = handle
+                    .request(|reply| GameCommand::Subscribe { session_id, reply })
+                    .await
+                    .ok_or_else(warp::reject::not_found)?;
+
+                Ok::<_, warp::Rejection>(warp::sse::reply(warp::sse::keep_alive().stream(async_stream::stream! {
+                    let mut rx = rx;
+                    while let Ok(message) = rx.recv().await {
+                        yield Ok::<_, warp::Error>(warp::sse::Event::default().data(message));
+                    }
+                })))
+            },
+        );
+
+
+    // Endpoint to handle cell clicks
+    let cell_click = warp::path!("cell-click" / usize)
+    .and(warp::post())
+    .and(warp::body::json())
+    .and(state_filter.clone())
+    .and_then(
+        |game_id: usize, click: CellClick, state: AppState| async move {
+            let username = match state.players.read().await.get(&click.session_id) {
+                Some((username, _)) => username.clone(),
+                None => return Ok::<_, warp::Rejection>(warp::reply::json(&serde_json::json!({
+                    "success": false,
+                    "error": "Session ID not found",
+                }))),
+            };
+
+            // The game's task processes the click and updates every viewer
+            let handle = state.games.read().await.get(game_id);
+            let reply = match handle {
+                Some(handle) => handle.request(|reply| GameCommand::Click { username, click, reply }).await,
+                None => None,
+            };
+            Ok::<_, warp::Rejection>(warp::reply::json(&reply.unwrap_or_else(|| serde_json::json!({
+                "success": false,
+                "error": "Game not found or inactive",
+            }))))
+        },
+    );
+
+
+    // Endpoint: Play a complete move in one request, for scripts, bots and
+    // front ends that do not click square by square
+    let submit_move = warp::path!("games" / usize / "moves")
+    .and(warp::post())
+    .and(warp::body::json())
+    .and(state_filter.clone())
+    .and_then(
+        |game_id: usize, submission: MoveSubmission, state: AppState| async move {
+            let username = match state.players.read().await.get(&submission.session_id) {
+                Some((username, _)) => username.clone(),
+                None => return Ok::<_, warp::Rejection>(warp::reply::json(&serde_json::json!({
+                    "success": false,
+                    "error": "Session ID not found",
+                }))),
+            };
+
+            let handle = state.games.read().await.get(game_id);
+            let reply = match handle {
+                Some(handle) => handle.request(|reply| GameCommand::Submit { username, submission, reply }).await,
+                None => None,
+            };
+            Ok::<_, warp::Rejection>(warp::reply::json(&reply.unwrap_or_else(|| serde_json::json!({
+                "success": false,
+                "error": "Game not found or inactive",
+            }))))
+        },
+    );
+
+    // Combine all routes
+    let routes = static_files
+        .or(username_form)
+        .or(main_page_get)
+        .or(main_page_post)
+        .or(sign_out_post)
+        .or(rules)
+        .or(new_game)
+        .or(cell_click)
+        .or(submit_move)
+        .or(hint)
+        .or(analysis_page)
+        .or(book_page)
+        .or(engine_lines)
+        .or(board_updates)
+        .or(join_game_by_id)
+        .or(redirect_to_game)
+        .or(redirect_endpoint)
+        .or(hnefatafl_redirect_local)
+        .or(hnefatafl_redirect_online)
+        .or(tablut_redirect_local)
+        .or(tablut_redirect_online)
+        .or(brandubh_redirect_local)
+        .or(brandubh_redirect_online)
+        .or(koch_redirect_local)
+        .or(koch_redirect_online)
+        .or(game_mode_local)
+        .or(game_mode_online)
+        .with(cors().allow_any_origin().allow_methods(vec![Method::GET, Method::POST]));
+
+
+    // Start the server
+    warp::serve(routes).run(([0, 0, 0, 0], 3030)).await;
+}
+#[cfg(test)]
+#[cfg(feature = "brandubh")]
+mod tests {
+    use super::*;
+
+    /// Coordinates of a Brandubh square, e.g. "d1"
+    fn square(name: &str) -> (usize, usize) {
+        parse_square(name, 7).unwrap()
+    }
+
+    fn test_state() -> AppState {
+        AppState {
+            games: Arc::default(),
+            players: Arc::default(),
+            player_game_map: Arc::default(),
+            analyses: Arc::default(),
+            archive: mpsc::unbounded_channel().0,
+        }
+    }
+
+    /// A game's task, driven directly rather than through its channel
+    fn actor(mode: GameMode) -> GameActor {
+        let (commands, _receiver) = mpsc::channel(GAME_COMMAND_QUEUE);
+        GameActor {
+            server_game: ServerGame::new(1, Variant::Brandubh, mode),
+            channels: HashMap::new(),
+            commands: commands.downgrade(),
+            state: test_state(),
+        }
+    }
+
+    /// A move submission as it arrives in a request body
+    fn submission(body: serde_json::Value) -> MoveSubmission {
+        serde_json::from_value(body).unwrap()
+    }
+
+    #[test]
+    fn viewers_share_the_position_but_not_their_selection() {
+        let mut server_game = ServerGame::new(1, Variant::Brandubh, GameMode::Local);
+        let unselected = server_game.render_for("bob");
+
+        server_game.click("alice", Perspective::BothSides, square("d1")).unwrap();
+        assert_eq!(server_game.views["alice"].selected, Some(square("d1")));
+        assert!(!server_game.views.contains_key("bob"));
+        assert_ne!(server_game.render_for("alice"), unselected);
+        assert_eq!(server_game.render_for("bob"), unselected);
+        assert_eq!(position_string(&server_game.game), position_string(&Game::new(Variant::Brandubh)));
+
+        // A move by anyone shows on every board and clears every selection
+        server_game.click("bob", Perspective::BothSides, square("a4")).unwrap();
+        server_game.click("alice", Perspective::BothSides, square("c1")).unwrap();
+        assert_eq!(server_game.ply, 1);
+        assert!(server_game.views.is_empty());
+        assert_eq!(server_game.render_for("alice"), server_game.render_for("bob"));
+        assert_eq!(server_game.game.history(), vec![Move::new(square("d1"), square("c1"))]);
+    }
+
+    #[test]
+    fn clicks_select_move_and_clear_per_viewer() {
+        let mut server_game = ServerGame::new(1, Variant::Brandubh, GameMode::Online);
+        server_game.take_seat("alice", Some(Side::Attacker));
+        server_game.take_seat("bob", None);
+        let alice = server_game.perspective("alice");
+        let bob = server_game.perspective("bob");
+        assert_eq!((alice, bob), (Perspective::Side(Side::Attacker), Perspective::Side(Side::Defender)));
+        assert_eq!(server_game.perspective("carol"), Perspective::Spectator);
+
+        // Selecting an own piece lists its targets; clicking elsewhere clears it
+        assert_eq!(server_game.click("alice", alice, square("d1")), Ok(None));
+        let mut targets = server_game.views["alice"].targets.clone();
+        targets.sort();
+        assert_eq!(targets, vec![square("b1"), square("c1"), square("e1"), square("f1")]);
+        assert_eq!(server_game.click("alice", alice, square("g7")), Ok(None));
+        assert_eq!(server_game.views["alice"].selected, None);
+
+        // Only the side to move may click, and never on a square off the board
+        assert_eq!(server_game.click("bob", bob, square("d3")), Err("Not your turn".to_string()));
+        assert_eq!(server_game.click("carol", Perspective::Spectator, square("d1")), Err("Spectators cannot move".to_string()));
+        assert!(server_game.click("alice", alice, (7, 0)).is_err());
+        assert!(server_game.views.get("bob").is_none_or(|view| view.selected.is_none()));
+
+        // Moving the selected piece hands the turn over
+        server_game.click("alice", alice, square("d1")).unwrap();
+        assert_eq!(server_game.click("alice", alice, square("c1")), Ok(Some(Move::new(square("d1"), square("c1")))));
+        assert_eq!(server_game.click("bob", bob, square("d3")), Ok(None));
+        assert_eq!(server_game.views["bob"].selected, Some(square("d3")));
+    }
+
+    #[test]
+    fn submitted_moves_are_played_or_refused_with_the_board() {
+        let mut actor = actor(GameMode::Local);
+        let reply = actor.submit("alice", &submission(serde_json::json!({ "session_id": "alice", "notation": "d1-c1" })));
+        assert_eq!(reply["success"], true);
+        assert_eq!(reply["move"], "d1-c1");
+        assert_eq!(reply["ply"], 1);
+        assert_eq!(reply["side_to_move"], "defender");
+        assert_eq!(reply["captured"], serde_json::json!([]));
+        assert_eq!(reply["position"], position_string(&actor.server_game.game));
+
+        // Squares may be given by name or by coordinates
+        let reply = actor.submit("alice", &submission(serde_json::json!({
+            "session_id": "alice",
+            "from": "d3",
+            "to": { "row": 4, "col": 1 },
+        })));
+        assert_eq!(reply["move"], "d3-b3");
+        assert_eq!(actor.server_game.ply, 2);
+
+        // A refused move leaves the game as it was and shows the board
+        for body in [
+            serde_json::json!({ "session_id": "alice", "notation": "a1-a2" }),
+            serde_json::json!({ "session_id": "alice", "notation": "d1" }),
+            serde_json::json!({ "session_id": "alice", "from": "d2" }),
+        ] {
+            let reply = actor.submit("alice", &submission(body));
+            assert_eq!(reply["success"], false);
+            assert!(reply["error"].is_string());
+            assert_eq!(reply["ply"], 2);
+            assert_eq!(reply["board_html"], actor.server_game.render_for("alice"));
+            assert_eq!(reply["board_message"], "Current turn: Attacker");
+        }
+        assert_eq!(actor.server_game.ply, 2);
+    }
+
+    #[test]
+    fn finished_games_go_to_the_archive_writer() {
+        let (archive, mut archived) = mpsc::unbounded_channel();
+        let mut actor = actor(GameMode::Local);
+        actor.state.archive = archive;
+        actor.server_game.game = Game::from_position(Variant::Brandubh, "7/7/7/7/7/k6/5a1 d").unwrap();
+
+        actor.submit("alice", &submission(serde_json::json!({ "session_id": "alice", "notation": "a2-a3" })));
+        assert!(archived.try_recv().is_err());
+
+        actor.submit("alice", &submission(serde_json::json!({ "session_id": "alice", "notation": "f1-f2" })));
+        let reply = actor.submit("alice", &submission(serde_json::json!({ "session_id": "alice", "notation": "a3-a1" })));
+        assert_eq!(reply["winner"], "defender");
+        assert_eq!(actor.server_game.status, GameStatus::Finished);
+        let (id, record) = archived.try_recv().unwrap();
+        assert_eq!(id, actor.server_game.id);
+        assert_eq!(record.winner, Some(Side::Defender));
+        assert!(archived.try_recv().is_err());
+    }
+
+    #[test]
+    fn requests_against_a_stale_ply_are_rejected() {
+        let mut actor = actor(GameMode::Local);
+        let first = serde_json::json!({ "session_id": "alice", "notation": "d1-c1", "expected_ply": 0 });
+        assert_eq!(actor.submit("alice", &submission(first.clone()))["success"], true);
+
+        // The same move sent twice, e.g. from a double click, is not replayed
+        let reply = actor.submit("alice", &submission(first));
+        assert_eq!(reply["success"], false);
+        assert_eq!(reply["conflict"], true);
+        assert_eq!(reply["ply"], 1);
+        assert_eq!(reply["position"], position_string(&actor.server_game.game));
+
+        // Clicks made on the old board are dropped without selecting anything
+        let (row, col) = square("d3");
+        let click = CellClick { row, col, session_id: "bob".to_string(), expected_ply: Some(0) };
+        assert_eq!(actor.click("bob", &click)["conflict"], true);
+        assert!(!actor.server_game.views.contains_key("bob"));
+
+        // Requests on the current ply, or without one, go through
+        let click = CellClick { expected_ply: Some(1), ..click };
+        assert_eq!(actor.click("bob", &click)["success"], true);
+        assert_eq!(actor.server_game.views["bob"].selected, Some(square("d3")));
+        let reply = actor.submit("bob", &submission(serde_json::json!({ "session_id": "bob", "notation": "d3-b3" })));
+        assert_eq!(reply["ply"], 2);
+        assert_eq!(actor.server_game.ply, 2);
+    }
+
+    #[test]
+    fn online_games_start_once_both_seats_are_taken() {
+        let mut server_game = ServerGame::new(1, Variant::Brandubh, GameMode::Online);
+        assert_eq!(server_game.status, GameStatus::Waiting);
+        assert_eq!(server_game.take_seat("alice", Some(Side::Defender)), Some(Side::Defender));
+        assert_eq!(server_game.take_seat("alice", Some(Side::Attacker)), Some(Side::Defender));
+        assert_eq!(server_game.status, GameStatus::Waiting);
+        assert_eq!(server_game.take_seat("bob", Some(Side::Defender)), Some(Side::Attacker));
+        assert_eq!(server_game.status, GameStatus::Active);
+        assert_eq!(server_game.take_seat("carol", None), None);
+
+        assert_eq!(ServerGame::new(2, Variant::Brandubh, GameMode::Local).status, GameStatus::Active);
+    }
+
+    #[test]
+    fn idle_games_are_abandoned_then_removed() {
+        let mut actor = actor(GameMode::Local);
+        let mut updates = actor.channels.entry("alice".to_string()).or_insert_with(|| broadcast::channel(100).0).subscribe();
+        actor.server_game.click("alice", Perspective::BothSides, square("d1")).unwrap();
+        let start = actor.server_game.last_activity;
+
+        assert!(!actor.expire(start + IDLE_GAME_TIMEOUT - Duration::from_secs(1)));
+        assert_eq!(actor.server_game.status, GameStatus::Active);
+
+        // Abandoning counts as the game's last activity and tells its viewers
+        let abandoned_at = start + IDLE_GAME_TIMEOUT;
+        assert!(!actor.expire(abandoned_at));
+        assert_eq!(actor.server_game.status, GameStatus::Abandoned);
+        assert!(actor.server_game.views.is_empty());
+        assert_eq!(actor.server_game.board_message(), "Game abandoned");
+        let update: serde_json::Value = serde_json::from_str(&updates.try_recv().unwrap()).unwrap();
+        assert_eq!(update["board_message"], "Game abandoned");
+        assert_eq!(
+            actor.server_game.click("alice", Perspective::BothSides, square("d1")),
+            Err("The game was abandoned".to_string())
+        );
+
+        let last_activity = actor.server_game.last_activity;
+        assert!(!actor.expire(last_activity + ENDED_GAME_RETENTION - Duration::from_secs(1)));
+        assert!(actor.expire(last_activity + ENDED_GAME_RETENTION));
+    }
+
+    #[test]
+    fn finished_and_waiting_games_expire() {
+        let mut waiting = actor(GameMode::Online);
+        let start = waiting.server_game.last_activity;
+        assert!(!waiting.expire(start + IDLE_GAME_TIMEOUT));
+        assert_eq!(waiting.server_game.status, GameStatus::Abandoned);
+
+        // Finished games are kept for their analysis, then removed
+        let mut finished = actor(GameMode::Local);
+        finished.server_game.status = GameStatus::Finished;
+        let start = finished.server_game.last_activity;
+        assert!(!finished.expire(start + IDLE_GAME_TIMEOUT));
+        assert_eq!(finished.server_game.status, GameStatus::Finished);
+        assert!(finished.expire(start + ENDED_GAME_RETENTION));
+    }
+
+    #[tokio::test]
+    async fn games_handle_their_requests_in_order() {
+        let state = test_state();
+        let id = spawn_game(&state, Variant::Brandubh, GameMode::Local).await;
+        let handle = state.games.read().await.get(id).unwrap();
+
+        // Queue a move, the same move again and a snapshot before reading any reply
+        let mut replies = Vec::new();
+        for _ in 0..2 {
+            let (reply, answer) = oneshot::channel();
+            let submission = submission(serde_json::json!({ "session_id": "alice", "notation": "d1-c1", "expected_ply": 0 }));
+            handle.commands.send(GameCommand::Submit { username: "alice".to_string(), submission, reply }).await.unwrap();
+            replies.push(answer);
+        }
+        let (reply, snapshot) = oneshot::channel();
+        handle.commands.send(GameCommand::Snapshot(reply)).await.unwrap();
+
+        assert_eq!(replies.remove(0).await.unwrap()["success"], true);
+        assert_eq!(replies.remove(0).await.unwrap()["conflict"], true);
+        let server_game = snapshot.await.unwrap();
+        assert_eq!(server_game.ply, 1);
+        assert_eq!(server_game.game.history(), vec![Move::new(square("d1"), square("c1"))]);
+
+        // Concurrent requests against the same ply: exactly one move is played
+        let requests = (0..8).map(|_| {
+            let handle = handle.clone();
+            tokio::spawn(async move {
+                let submission = submission(serde_json::json!({ "session_id": "bob", "notation": "d3-b3", "expected_ply": 1 }));
+                handle.request(|reply| GameCommand::Submit { username: "bob".to_string(), submission, reply }).await.unwrap()
+            })
+        });
+        let mut played = 0;
+        for request in requests.collect::<Vec<_>>() {
+            if request.await.unwrap()["success"] == true {
+                played += 1;
+            }
+        }
+        assert_eq!(played, 1);
+        assert_eq!(state.snapshot(id).await.unwrap().ply, 2);
+        assert_eq!(handle.request(|reply| GameCommand::Join { session_id: "bob".to_string(), reply }).await, Some(JoinOutcome::NotOnline));
+    }
+
+    #[tokio::test]
+    async fn games_forget_themselves_when_their_task_ends() {
+        let state = test_state();
+        let id = spawn_game(&state, Variant::Brandubh, GameMode::Online).await;
+        state.player_game_map.write().await.insert("alice".to_string(), id);
+        let handle = state.games.read().await.get(id).unwrap();
+        assert_eq!(
+            handle.request(|reply| GameCommand::Join { session_id: "alice".to_string(), reply }).await,
+            Some(JoinOutcome::Seated(Side::Attacker))
+        );
+
+        // Dropping every handle closes the game's channel and ends its task
+        drop(handle);
+        drop(state.games.write().await.games.remove(&id));
+        for _ in 0..100 {
+            if state.player_game_map.read().await.is_empty() {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        assert!(state.player_game_map.read().await.is_empty());
+        assert!(state.snapshot(id).await.is_none());
+    }
+
+    #[tokio::test]
+    async fn visitors_of_a_full_online_game_watch_it() {
+        let state = test_state();
+        for (session_id, role) in [("alice", "defender"), ("bob", "local"), ("carol", "attacker")] {
+            state.players.write().await.insert(session_id.to_string(), (session_id.to_string(), role.to_string()));
+        }
+        let id = spawn_game(&state, Variant::Brandubh, GameMode::Online).await;
+        let handle = state.games.read().await.get(id).unwrap();
+        let join = |session_id: &str| {
+            let session_id = session_id.to_string();
+            let handle = handle.clone();
+            async move { handle.request(|reply| GameCommand::Join { session_id, reply }).await }
+        };
+
+        // The creator takes their side on their first visit, the next
+        // player the other side, and a third visitor finds the game full
+        let page = game_page(&state, id, Some("alice".to_string())).await;
+        assert_eq!(page.status(), 200);
+        assert_eq!(join("bob").await, Some(JoinOutcome::Seated(Side::Attacker)));
+        assert_eq!(join("carol").await, Some(JoinOutcome::Full));
+
+        let engine_panel = r#"id="engine-panel""#;
+        let page = game_page(&state, id, Some("carol".to_string())).await;
+        assert_eq!(page.status(), 200);
+        assert!(page.body().contains("<table>"));
+        assert!(page.body().contains(engine_panel));
+        assert!(page.body().contains("<p>bob (attacker)</p><p>alice (defender)</p>"));
+        assert!(!page.body().contains("carol ("));
+
+        // Players keep their page and do not see the engine
+        for player in ["alice", "bob"] {
+            let page = game_page(&state, id, Some(player.to_string())).await;
+            assert_eq!(page.status(), 200);
+            assert!(!page.body().contains(engine_panel));
+        }
+        let mapping = state.player_game_map.read().await;
+        assert_eq!((mapping.get("alice"), mapping.get("bob"), mapping.get("carol")), (Some(&id), Some(&id), None));
+        drop(mapping);
+
+        // Visitors without a session watch too
+        let page = game_page(&state, id, None).await;
+        assert_eq!(page.status(), 200);
+        assert!(page.body().contains(engine_panel));
+        assert_eq!(game_page(&state, id + 1, None).await.status(), 404);
+    }
+}
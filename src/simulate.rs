@@ -0,0 +1,364 @@
+// Batch simulation for rules balancing: many games between two policies,
+// played on all CPU cores, summarised per variant (results, length,
+// captures, how games ended) and optionally exported position by position.
+
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::thread;
+
+use crate::bot::{create_bot, Bot, GreedyBot, RandomBot};
+use crate::engine::{position_string, Game, Piece, Pieces, Rules, Side, Variant};
+use crate::eval::Weights;
+use crate::tournament::Entrant;
+
+/// Games reaching this length are scored as draws, unless configured otherwise
+pub const DEFAULT_MAX_PLIES: usize = 300;
+
+/// How a simulated game player chooses its moves
+#[derive(Debug, Clone)]
+pub enum Policy {
+    Random,                 // Any legal move
+    Greedy,                 // Best static evaluation one ply ahead
+    Engine(Entrant),        // Built-in or external engine at a level
+}
+
+impl Policy {
+    /// Parses "random", "greedy" or an engine, e.g. "alphabeta/3".
+    pub fn parse(spec: &str) -> Result<Policy, String> {
+        match spec {
+            "random" => Ok(Policy::Random),
+            "greedy" => Ok(Policy::Greedy),
+            _ => Entrant::parse(spec).map(Policy::Engine),
+        }
+    }
+
+    /// Builds a bot following the policy for one side of a game.
+    pub fn create(&self, side: Side, variant: Variant, think_time_ms: u64) -> Box<dyn Bot> {
+        match self {
+            Policy::Random => Box::new(RandomBot::default()),
+            Policy::Greedy => Box::new(GreedyBot::new(Weights::for_variant(variant))),
+            Policy::Engine(entrant) => create_bot(&entrant.config(side, think_time_ms), variant),
+        }
+    }
+}
+
+/// Why a simulated game ended
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Termination {
+    KingEscaped,
+    KingCaptured,
+    DefendersImmobilized,   // The defenders had no legal move left
+    NoLegalMove,            // The side to move could not move and the rules name no winner
+    MoveLimit,
+}
+
+impl Termination {
+    pub const ALL: [Termination; 5] = [
+        Termination::KingEscaped,
+        Termination::KingCaptured,
+        Termination::DefendersImmobilized,
+        Termination::NoLegalMove,
+        Termination::MoveLimit,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Termination::KingEscaped => "king_escaped",
+            Termination::KingCaptured => "king_captured",
+            Termination::DefendersImmobilized => "defenders_immobilized",
+            Termination::NoLegalMove => "no_legal_move",
+            Termination::MoveLimit => "move_limit",
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct SimulationConfig {
+    pub variant: Variant,
+    pub games: usize,
+    pub max_plies: usize,
+    pub record_positions: bool,     // Keep every position for the dataset export
+}
+
+/// Result of one simulated game
+#[derive(Debug, Clone)]
+pub struct GameSummary {
+    pub winner: Option<Side>,
+    pub plies: usize,
+    pub attacker_captures: usize,
+    pub defender_captures: usize,
+    pub termination: Termination,
+    pub positions: Vec<String>,     // Positions before each move, when recorded
+}
+
+/// Aggregate results of the games of one variant
+#[derive(Debug, Clone, Serialize)]
+pub struct SimulationStats {
+    pub variant: Variant,
+    pub games: usize,
+    pub attacker_wins: usize,
+    pub defender_wins: usize,
+    pub draws: usize,
+    pub attacker_win_rate: f64,
+    pub defender_win_rate: f64,
+    pub average_length: f64,
+    pub average_attacker_captures: f64,
+    pub average_defender_captures: f64,
+    pub terminations: BTreeMap<&'static str, usize>,
+}
+
+impl SimulationStats {
+    pub fn from_games(variant: Variant, games: &[GameSummary]) -> SimulationStats {
+        let count = games.len();
+        let average = |value: usize| value as f64 / count.max(1) as f64;
+        let wins = |side: Side| games.iter().filter(|game| game.winner == Some(side)).count();
+        let (attacker_wins, defender_wins) = (wins(Side::Attacker), wins(Side::Defender));
+        let terminations = Termination::ALL
+            .iter()
+            .map(|&termination| (termination.name(), games.iter().filter(|game| game.termination == termination).count()))
+            .collect();
+
+        SimulationStats {
+            variant,
+            games: count,
+            attacker_wins,
+            defender_wins,
+            draws: count - attacker_wins - defender_wins,
+            attacker_win_rate: average(attacker_wins),
+            defender_win_rate: average(defender_wins),
+            average_length: average(games.iter().map(|game| game.plies).sum()),
+            average_attacker_captures: average(games.iter().map(|game| game.attacker_captures).sum()),
+            average_defender_captures: average(games.iter().map(|game| game.defender_captures).sum()),
+            terminations,
+        }
+    }
+
+    /// CSV header matching `csv_row`
+    pub fn csv_header() -> String {
+        let mut columns = vec![
+            "variant",
+            "games",
+            "attacker_wins",
+            "defender_wins",
+            "draws",
+            "attacker_win_rate",
+            "defender_win_rate",
+            "average_length",
+            "average_attacker_captures",
+            "average_defender_captures",
+        ];
+        columns.extend(Termination::ALL.iter().map(Termination::name));
+        columns.join(",")
+    }
+
+    pub fn csv_row(&self) -> String {
+        let mut values = vec![
+            self.variant.name().to_string(),
+            self.games.to_string(),
+            self.attacker_wins.to_string(),
+            self.defender_wins.to_string(),
+            self.draws.to_string(),
+            format!("{:.4}", self.attacker_win_rate),
+            format!("{:.4}", self.defender_win_rate),
+            format!("{:.2}", self.average_length),
+            format!("{:.3}", self.average_attacker_captures),
+            format!("{:.3}", self.average_defender_captures),
+        ];
+        values.extend(Termination::ALL.iter().map(|termination| self.terminations[termination.name()].to_string()));
+        values.join(",")
+    }
+}
+
+/// Tells a king capture from defenders left without a move, once the
+/// attackers have won.
+fn attacker_win_reason(game: &Game) -> Termination {
+    let size = game.size();
+    let pieces: Pieces = (0..size).map(|row| (0..size).map(|col| game.piece_at((row, col))).collect()).collect();
+    if !pieces.iter().flatten().any(|&piece| piece == Some(Piece::King)) {
+        return Termination::KingCaptured;
+    }
+    let mut defenders_to_move = Game::new(game.variant());
+    match defenders_to_move.set_position(&pieces, Side::Defender) {
+        Ok(()) if defenders_to_move.legal_moves().is_empty() => Termination::DefendersImmobilized,
+        _ => Termination::KingCaptured,
+    }
+}
+
+fn play_game(config: &SimulationConfig, bots: &mut [Box<dyn Bot>; 2]) -> GameSummary {
+    let mut game = Game::new(config.variant);
+    let mut captures = [0, 0];
+    let mut positions = Vec::new();
+    let mut stuck = false;
+
+    while game.winner().is_none() && game.history().len() < config.max_plies {
+        let side = game.side_to_move();
+        let index = if side == Side::Attacker { 0 } else { 1 };
+        if config.record_positions {
+            positions.push(position_string(&game));
+        }
+        match bots[index].choose_move(&game).map(|mv| game.play(mv)) {
+            Some(Ok(captured)) => captures[index] += captured.len(),
+            _ => {
+                stuck = true;
+                break;
+            }
+        }
+    }
+
+    let termination = match game.winner() {
+        Some(Side::Defender) => Termination::KingEscaped,
+        Some(Side::Attacker) => attacker_win_reason(&game),
+        None if stuck => Termination::NoLegalMove,
+        None => Termination::MoveLimit,
+    };
+    GameSummary {
+        winner: game.winner(),
+        plies: game.history().len(),
+        attacker_captures: captures[0],
+        defender_captures: captures[1],
+        termination,
+        positions,
+    }
+}
+
+/// Plays the configured number of games on all CPU cores, with bots built
+/// by `make_bot` for each side of each game.
+pub fn simulate(config: &SimulationConfig, make_bot: impl Fn(Side) -> Box<dyn Bot> + Sync) -> Vec<GameSummary> {
+    let next_game = AtomicUsize::new(0);
+    let summaries = Mutex::new(Vec::with_capacity(config.games));
+    let threads = thread::available_parallelism().map_or(1, |count| count.get()).min(config.games.max(1));
+
+    thread::scope(|scope| {
+        for _ in 0..threads {
+            scope.spawn(|| {
+                while next_game.fetch_add(1, Ordering::Relaxed) < config.games {
+                    let mut bots = [make_bot(Side::Attacker), make_bot(Side::Defender)];
+                    let summary = play_game(config, &mut bots);
+                    summaries.lock().unwrap().push(summary);
+                }
+            });
+        }
+    });
+
+    summaries.into_inner().unwrap()
+}
+
+/// Writes one CSV line per recorded position with the final result of its
+/// game: variant, game number, ply, position and winner ("draw" if none).
+pub fn write_dataset(path: &str, datasets: &[(Variant, Vec<GameSummary>)]) -> Result<usize, String> {
+    let file = File::create(path).map_err(|error| format!("Cannot create {}: {}", path, error))?;
+    let mut writer = BufWriter::new(file);
+    let write_error = |error: std::io::Error| format!("Cannot write {}: {}", path, error);
+    let mut rows = 0;
+
+    writeln!(writer, "variant,game,ply,position,winner").map_err(write_error)?;
+    for (variant, games) in datasets {
+        for (index, game) in games.iter().enumerate() {
+            let winner = game.winner.map_or("draw", |side| side.name());
+            for (ply, position) in game.positions.iter().enumerate() {
+                writeln!(writer, "{},{},{},{},{}", variant, index + 1, ply, position, winner).map_err(write_error)?;
+                rows += 1;
+            }
+        }
+    }
+    writer.flush().map_err(write_error)?;
+    Ok(rows)
+}
+
+#[cfg(test)]
+#[cfg(feature = "brandubh")]
+mod tests {
+    use super::*;
+
+    fn summary(winner: Option<Side>, plies: usize, captures: [usize; 2], termination: Termination) -> GameSummary {
+        GameSummary {
+            winner,
+            plies,
+            attacker_captures: captures[0],
+            defender_captures: captures[1],
+            termination,
+            positions: Vec::new(),
+        }
+    }
+
+    fn stats() -> SimulationStats {
+        SimulationStats::from_games(Variant::Brandubh, &[
+            summary(Some(Side::Attacker), 40, [3, 1], Termination::KingCaptured),
+            summary(Some(Side::Defender), 20, [1, 2], Termination::KingEscaped),
+            summary(Some(Side::Defender), 30, [2, 0], Termination::KingEscaped),
+            summary(None, 300, [6, 5], Termination::MoveLimit),
+        ])
+    }
+
+    #[test]
+    fn stats_are_exported_as_json() {
+        let json = serde_json::to_value(stats()).unwrap();
+        assert_eq!(json["variant"], "brandubh");
+        assert_eq!(json["games"], 4);
+        assert_eq!((json["attacker_wins"].as_u64(), json["defender_wins"].as_u64(), json["draws"].as_u64()), (Some(1), Some(2), Some(1)));
+        assert_eq!(json["attacker_win_rate"], 0.25);
+        assert_eq!(json["defender_win_rate"], 0.5);
+        assert_eq!(json["average_length"], 97.5);
+        assert_eq!(json["average_attacker_captures"], 3.0);
+        assert_eq!(json["average_defender_captures"], 2.0);
+        assert_eq!(json["terminations"], serde_json::json!({
+            "king_escaped": 2,
+            "king_captured": 1,
+            "defenders_immobilized": 0,
+            "no_legal_move": 0,
+            "move_limit": 1,
+        }));
+    }
+
+    #[test]
+    fn stats_are_exported_as_csv() {
+        let header = SimulationStats::csv_header();
+        let row = stats().csv_row();
+        let columns: Vec<&str> = header.split(',').collect();
+        let values: Vec<&str> = row.split(',').collect();
+        assert_eq!(columns.len(), values.len());
+        let field = |name: &str| values[columns.iter().position(|&column| column == name).unwrap()];
+        assert_eq!(field("variant"), "brandubh");
+        assert_eq!(field("draws"), "1");
+        assert_eq!(field("attacker_win_rate"), "0.2500");
+        assert_eq!(field("average_length"), "97.50");
+        assert_eq!(field("average_defender_captures"), "2.000");
+        assert_eq!(field("king_escaped"), "2");
+        assert_eq!(field("move_limit"), "1");
+    }
+
+    #[test]
+    fn simulated_games_and_their_dataset_are_consistent() {
+        let config = SimulationConfig { variant: Variant::Brandubh, games: 6, max_plies: 40, record_positions: true };
+        let games = simulate(&config, |side| Policy::Random.create(side, Variant::Brandubh, 0));
+        assert_eq!(games.len(), 6);
+        for game in &games {
+            assert!(game.plies <= 40);
+            match game.termination {
+                Termination::KingEscaped => assert_eq!(game.winner, Some(Side::Defender)),
+                Termination::KingCaptured | Termination::DefendersImmobilized => assert_eq!(game.winner, Some(Side::Attacker)),
+                Termination::MoveLimit => assert_eq!((game.winner, game.plies), (None, 40)),
+                Termination::NoLegalMove => assert_eq!(game.winner, None),
+            }
+            assert_eq!(game.positions.len(), game.plies + usize::from(game.termination == Termination::NoLegalMove));
+            assert_eq!(game.positions[0], position_string(&Game::new(Variant::Brandubh)));
+        }
+
+        let path = std::env::temp_dir().join(format!("simulate-test-{}.csv", std::process::id()));
+        let path = path.to_str().unwrap();
+        let rows = write_dataset(path, &[(Variant::Brandubh, games.clone())]).unwrap();
+        let text = std::fs::read_to_string(path).unwrap();
+        std::fs::remove_file(path).unwrap();
+
+        let lines: Vec<&str> = text.lines().collect();
+        assert_eq!(lines[0], "variant,game,ply,position,winner");
+        assert_eq!(rows, games.iter().map(|game| game.positions.len()).sum::<usize>());
+        assert_eq!(lines.len(), rows + 1);
+        let winner = games[0].winner.map_or("draw", |side| side.name());
+        assert_eq!(lines[1], format!("brandubh,1,0,{},{}", games[0].positions[0], winner));
+    }
+}
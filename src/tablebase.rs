@@ -0,0 +1,469 @@
+// Endgame tablebase for Brandubh. Every position with the king and a few
+// other pieces is solved by retrograde analysis: positions are resolved ply
+// by ply from the ones the game ends in, giving the exact result and the
+// distance to the end of the game with perfect play.
+//
+// Only decisive positions are stored, keyed by Zobrist hash; a covered
+// position missing from the table is a draw.
+
+use std::collections::HashMap;
+use std::fs;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Mutex, OnceLock};
+use std::thread;
+
+use crate::engine::{Game, Move, Piece, Pieces, Rules, Side, Variant};
+
+/// Tablebase file written by the generator and loaded at startup
+pub const TABLEBASE_FILE: &str = "brandubh.tb";
+
+/// Pieces, king included, in the positions the generator solves by default
+pub const DEFAULT_PIECES: usize = 3;
+
+/// Largest piece count the generator accepts. Each extra piece multiplies
+/// the number of positions by about forty.
+pub const MAX_PIECES: usize = 4;
+
+/// The variant solved, when Brandubh is compiled in
+#[cfg(feature = "brandubh")]
+const VARIANT: Option<Variant> = Some(Variant::Brandubh);
+#[cfg(not(feature = "brandubh"))]
+const VARIANT: Option<Variant> = None;
+
+const SIZE: usize = 7;
+const MAGIC: &[u8; 8] = b"TAFLTB01";
+
+/// Successor markers: the move ends the game in the mover's favour or in
+/// the opponent's, or leads to a position outside the table
+const WINS_NOW: u32 = u32::MAX;
+const UNKNOWN: u32 = u32::MAX - 1;
+const LOSES_NOW: u32 = u32::MAX - 2;
+
+/// Result of a position for the side to move, with the number of plies
+/// until the game ends under perfect play. `Loss(0)` means the side to
+/// move has no legal move.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Outcome {
+    Win(u16),
+    Loss(u16),
+    Draw,
+}
+
+impl Outcome {
+    /// The outcome for the player who just moved into the position
+    pub fn for_previous_mover(self) -> Outcome {
+        match self {
+            Outcome::Win(plies) => Outcome::Loss(plies + 1),
+            Outcome::Loss(plies) => Outcome::Win(plies + 1),
+            Outcome::Draw => Outcome::Draw,
+        }
+    }
+
+    /// Orders outcomes from the mover's point of view: quick wins first,
+    /// then draws, then the slowest losses.
+    pub fn rank(self) -> (u8, i32) {
+        match self {
+            Outcome::Win(plies) => (2, -(plies as i32)),
+            Outcome::Draw => (1, 0),
+            Outcome::Loss(plies) => (0, plies as i32),
+        }
+    }
+
+    /// Describes the outcome for the side to move, e.g. "Attackers win in 5 plies".
+    pub fn describe(self, side_to_move: Side) -> String {
+        let (winner, plies) = match self {
+            Outcome::Win(plies) => (side_to_move, plies),
+            Outcome::Loss(plies) => (side_to_move.opponent(), plies),
+            Outcome::Draw => return "Draw".to_string(),
+        };
+        let winner = match winner {
+            Side::Attacker => "Attackers",
+            Side::Defender => "Defenders",
+        };
+        match plies {
+            0 => format!("{} win", winner),
+            1 => format!("{} win in 1 ply", winner),
+            _ => format!("{} win in {} plies", winner, plies),
+        }
+    }
+
+    fn encode(self) -> i16 {
+        match self {
+            Outcome::Win(plies) => plies as i16,
+            Outcome::Loss(plies) => -(plies as i16) - 1,
+            Outcome::Draw => 0,
+        }
+    }
+
+    fn decode(value: i16) -> Outcome {
+        match value {
+            0 => Outcome::Draw,
+            value if value > 0 => Outcome::Win(value as u16),
+            value => Outcome::Loss((-value - 1) as u16),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Tablebase {
+    max_pieces: usize,
+    entries: Vec<(u64, i16)>,   // Decisive positions, sorted by hash
+}
+
+/// Tablebase loaded at startup, consulted by the bots and the analysis
+static TABLEBASE: OnceLock<Tablebase> = OnceLock::new();
+
+impl Tablebase {
+    /// Exact result of a Brandubh position the table covers, for the side
+    /// to move.
+    pub fn probe(&self, game: &Game) -> Option<Outcome> {
+        if Some(game.variant()) != VARIANT || game.winner().is_some() {
+            return None;
+        }
+        let mut pieces = 0;
+        let mut has_king = false;
+        for row in 0..SIZE {
+            for col in 0..SIZE {
+                match game.piece_at((row, col)) {
+                    Some(Piece::King) => {
+                        has_king = true;
+                        pieces += 1;
+                    }
+                    Some(_) => pieces += 1,
+                    None => {}
+                }
+            }
+        }
+        if !has_king || pieces > self.max_pieces {
+            return None;
+        }
+        let hash = game.position_hash();
+        Some(match self.entries.binary_search_by_key(&hash, |&(key, _)| key) {
+            Ok(index) => Outcome::decode(self.entries[index].1),
+            Err(_) => Outcome::Draw,
+        })
+    }
+
+    /// Outcome of every legal move for the side to move, if the table covers
+    /// the position.
+    pub fn move_outcomes(&self, game: &Game) -> Option<Vec<(Move, Outcome)>> {
+        self.probe(game)?;
+        let side = game.side_to_move();
+        let mut outcomes = Vec::new();
+        for mv in game.legal_moves() {
+            let mut next = game.clone();
+            next.play(mv).ok()?;
+            let outcome = match next.winner() {
+                Some(winner) if winner == side => Outcome::Win(1),
+                Some(_) => Outcome::Loss(1),
+                None => self.probe(&next)?.for_previous_mover(),
+            };
+            outcomes.push((mv, outcome));
+        }
+        Some(outcomes)
+    }
+
+    pub fn read(path: &str) -> Result<Tablebase, String> {
+        let bytes = fs::read(path).map_err(|error| format!("Cannot read {}: {}", path, error))?;
+        let invalid = || format!("Invalid tablebase file {}", path);
+        if bytes.len() < 17 || &bytes[..8] != MAGIC {
+            return Err(invalid());
+        }
+        let max_pieces = bytes[8] as usize;
+        let count = u64::from_le_bytes(bytes[9..17].try_into().map_err(|_| invalid())?) as usize;
+        let body = &bytes[17..];
+        if count.checked_mul(10) != Some(body.len()) {
+            return Err(invalid());
+        }
+        let entries = body
+            .chunks_exact(10)
+            .map(|entry| {
+                let hash = u64::from_le_bytes(entry[..8].try_into().unwrap());
+                let value = i16::from_le_bytes(entry[8..].try_into().unwrap());
+                (hash, value)
+            })
+            .collect();
+        Ok(Tablebase { max_pieces, entries })
+    }
+
+    pub fn write(&self, path: &str) -> Result<(), String> {
+        let mut bytes = Vec::with_capacity(17 + self.entries.len() * 10);
+        bytes.extend_from_slice(MAGIC);
+        bytes.push(self.max_pieces as u8);
+        bytes.extend_from_slice(&(self.entries.len() as u64).to_le_bytes());
+        for &(hash, value) in &self.entries {
+            bytes.extend_from_slice(&hash.to_le_bytes());
+            bytes.extend_from_slice(&value.to_le_bytes());
+        }
+        fs::write(path, bytes).map_err(|error| format!("Cannot write {}: {}", path, error))
+    }
+}
+
+/// Loads the tablebase used by the bots and the analysis, if the file
+/// exists, and returns the number of pieces it covers.
+pub fn load_tablebase(path: &str) -> Result<usize, String> {
+    if !std::path::Path::new(path).exists() {
+        return Ok(0);
+    }
+    let tablebase = Tablebase::read(path)?;
+    let pieces = tablebase.max_pieces;
+    TABLEBASE.set(tablebase).map_err(|_| "The tablebase is already loaded".to_string())?;
+    Ok(pieces)
+}
+
+/// The tablebase loaded at startup, if any
+pub fn tablebase() -> Option<&'static Tablebase> {
+    TABLEBASE.get()
+}
+
+fn is_corner(square: usize) -> bool {
+    let (row, col) = (square / SIZE, square % SIZE);
+    (row == 0 || row == SIZE - 1) && (col == 0 || col == SIZE - 1)
+}
+
+/// Calls `visit` with every position that has the king on `king_square`
+/// and at most `max_pieces - 1` other pieces, with either side to move.
+/// Only the king may stand on a corner, and a king on a corner has already
+/// escaped, so corners stay empty.
+fn for_each_position(king_square: usize, max_pieces: usize, mut visit: impl FnMut(&Pieces, Side)) {
+    let squares: Vec<usize> = (0..SIZE * SIZE).filter(|&square| !is_corner(square) && square != king_square).collect();
+    let mut board = vec![vec![None; SIZE]; SIZE];
+    board[king_square / SIZE][king_square % SIZE] = Some(Piece::King);
+
+    fn place(
+        board: &mut Pieces,
+        squares: &[usize],
+        start: usize,
+        remaining: usize,
+        visit: &mut dyn FnMut(&Pieces, Side),
+    ) {
+        for side in [Side::Attacker, Side::Defender] {
+            visit(board, side);
+        }
+        if remaining == 0 {
+            return;
+        }
+        for index in start..squares.len() {
+            let (row, col) = (squares[index] / SIZE, squares[index] % SIZE);
+            for piece in [Piece::Attacker, Piece::Defender] {
+                board[row][col] = Some(piece);
+                place(board, squares, index + 1, remaining - 1, visit);
+            }
+            board[row][col] = None;
+        }
+    }
+
+    place(&mut board, &squares, 0, max_pieces - 1, &mut visit);
+}
+
+/// Solves every Brandubh position with the king and up to `max_pieces - 1`
+/// other pieces. `report` receives progress messages.
+pub fn generate(max_pieces: usize, mut report: impl FnMut(&str)) -> Result<Tablebase, String> {
+    if !(1..=MAX_PIECES).contains(&max_pieces) {
+        return Err(format!("The tablebase covers 1 to {} pieces", MAX_PIECES));
+    }
+    let variant = VARIANT.ok_or("The tablebase needs the brandubh feature")?;
+    let king_squares: Vec<usize> = (0..SIZE * SIZE).filter(|&square| !is_corner(square)).collect();
+
+    // Index every position by its hash
+    let mut game = Game::new(variant);
+    let mut hashes = Vec::new();
+    for &king_square in &king_squares {
+        for_each_position(king_square, max_pieces, |board, side| {
+            if game.set_position(board, side).is_ok() {
+                hashes.push(game.position_hash());
+            }
+        });
+    }
+    let index: HashMap<u64, u32> = hashes.iter().enumerate().map(|(position, &hash)| (hash, position as u32)).collect();
+    report(&format!("Indexed {} positions", hashes.len()));
+
+    // Successors of every position, generated on all CPU cores one king
+    // square at a time and joined back in enumeration order
+    let next_square = AtomicUsize::new(0);
+    let blocks = Mutex::new(Vec::new());
+    let threads = thread::available_parallelism().map_or(1, |count| count.get());
+    thread::scope(|scope| {
+        for _ in 0..threads {
+            scope.spawn(|| {
+                let mut game = Game::new(variant);
+                loop {
+                    let square_index = next_square.fetch_add(1, Ordering::Relaxed);
+                    let Some(&king_square) = king_squares.get(square_index) else {
+                        break;
+                    };
+                    let mut lengths = Vec::new();
+                    let mut successors = Vec::new();
+                    for_each_position(king_square, max_pieces, |board, side| {
+                        if game.set_position(board, side).is_err() {
+                            return;
+                        }
+                        let before = successors.len();
+                        for mv in game.legal_moves() {
+                            if game.play(mv).is_err() {
+                                continue;
+                            }
+                            successors.push(match game.winner() {
+                                Some(winner) if winner == side => WINS_NOW,
+                                Some(_) => LOSES_NOW,
+                                None => index.get(&game.position_hash()).copied().unwrap_or(UNKNOWN),
+                            });
+                            let _ = game.undo();
+                        }
+                        lengths.push((successors.len() - before) as u32);
+                    });
+                    blocks.lock().unwrap().push((square_index, lengths, successors));
+                }
+            });
+        }
+    });
+    let mut blocks = blocks.into_inner().unwrap();
+    blocks.sort_by_key(|&(square_index, _, _)| square_index);
+
+    let mut offsets = Vec::with_capacity(hashes.len() + 1);
+    let mut successors = Vec::new();
+    offsets.push(0);
+    for (_, lengths, block) in blocks {
+        let mut offset = successors.len();
+        for length in lengths {
+            offset += length as usize;
+            offsets.push(offset);
+        }
+        successors.extend(block);
+    }
+    report(&format!("Generated {} moves", successors.len()));
+
+    let outcomes = solve(&offsets, &successors, &mut report);
+
+    let mut entries: Vec<(u64, i16)> = hashes
+        .iter()
+        .zip(&outcomes)
+        .filter_map(|(&hash, outcome)| outcome.map(|outcome| (hash, outcome.encode())))
+        .collect();
+    entries.sort_unstable_by_key(|&(hash, _)| hash);
+    report(&format!("{} decisive positions, {} draws", entries.len(), hashes.len() - entries.len()));
+    Ok(Tablebase { max_pieces, entries })
+}
+
+/// Retrograde passes over the moves of every position, `offsets` giving the
+/// range of each position's successors: a position is won in `ply` plies if
+/// a move leads to a loss in `ply - 1`, and lost in `ply` if every move
+/// leads to a win in fewer plies, a move losing at once counting as a win
+/// in 0 for the opponent. Positions still unresolved when a pass finds
+/// nothing new are draws.
+fn solve(offsets: &[usize], successors: &[u32], mut report: impl FnMut(&str)) -> Vec<Option<Outcome>> {
+    let positions = offsets.len() - 1;
+    let mut outcomes: Vec<Option<Outcome>> = (0..positions)
+        .map(|position| (offsets[position] == offsets[position + 1]).then_some(Outcome::Loss(0)))
+        .collect();
+    let mut ply: u16 = 1;
+    loop {
+        let mut resolved = 0;
+        for position in 0..positions {
+            if outcomes[position].is_some() {
+                continue;
+            }
+            let mut wins = false;
+            let mut all_lost = true;
+            for &successor in &successors[offsets[position]..offsets[position + 1]] {
+                match successor {
+                    WINS_NOW => wins = true,
+                    LOSES_NOW => {}
+                    UNKNOWN => all_lost = false,
+                    successor => match outcomes[successor as usize] {
+                        Some(Outcome::Loss(plies)) if plies + 1 == ply => wins = true,
+                        Some(Outcome::Win(plies)) if plies < ply => {}
+                        _ => all_lost = false,
+                    },
+                }
+            }
+            if wins {
+                outcomes[position] = Some(Outcome::Win(ply));
+                resolved += 1;
+            } else if all_lost {
+                outcomes[position] = Some(Outcome::Loss(ply));
+                resolved += 1;
+            }
+        }
+        if resolved == 0 {
+            break;
+        }
+        report(&format!("Ply {}: {} positions resolved", ply, resolved));
+        ply += 1;
+    }
+    outcomes
+}
+
+#[cfg(test)]
+#[cfg(feature = "brandubh")]
+mod tests {
+    use super::*;
+
+    fn position(text: &str) -> Game {
+        Game::from_position(Variant::Brandubh, text).unwrap()
+    }
+
+    #[test]
+    fn generated_tables_solve_small_endgames() {
+        let tablebase = generate(2, |_| {}).unwrap();
+
+        // The king next to a free corner escapes at once
+        let escape = position("7/7/7/7/7/k6/5a1 d");
+        assert_eq!(tablebase.probe(&escape), Some(Outcome::Win(1)));
+        let outcomes = tablebase.move_outcomes(&escape).unwrap();
+        let best = outcomes.iter().max_by_key(|(_, outcome)| outcome.rank()).unwrap();
+        assert_eq!(best.0, Move::parse("a2-a1", SIZE).unwrap());
+        assert_eq!(Outcome::Win(1).describe(Side::Defender), "Defenders win in 1 ply");
+
+        // Each position's result is the best result of its moves
+        for text in ["7/7/7/3k3/7/7/1a5 a", "7/7/7/3k3/7/7/1a5 d", "7/1a5/7/7/4k2/7/7 a", "7/7/2a4/7/4k2/7/7 d"] {
+            let game = position(text);
+            let best = tablebase.move_outcomes(&game).unwrap().into_iter().map(|(_, outcome)| outcome).max_by_key(|outcome| outcome.rank());
+            assert_eq!(tablebase.probe(&game), best, "{}", text);
+        }
+
+        // A move handing the opponent the win loses in 1 ply. Brandubh moves
+        // can always be taken back, so no position of the table has one and
+        // the solver gets a hand-built move list.
+        let offsets = [0, 1, 3, 5];
+        let successors = [LOSES_NOW, LOSES_NOW, 0, LOSES_NOW, UNKNOWN];
+        assert_eq!(solve(&offsets, &successors, |_| {}), [Some(Outcome::Loss(1)), Some(Outcome::Win(2)), None]);
+
+        // Positions with more pieces are not covered
+        assert_eq!(tablebase.probe(&Game::new(Variant::Brandubh)), None);
+        assert_eq!(tablebase.probe(&position("7/7/7/3a3/7/7/a2k3 d")), None);
+    }
+
+    #[test]
+    fn tables_read_back_as_written() {
+        let tablebase = generate(2, |_| {}).unwrap();
+        let path = std::env::temp_dir().join(format!("tablebase-test-{}.tb", std::process::id()));
+        let path = path.to_str().unwrap();
+        tablebase.write(path).unwrap();
+        let read = Tablebase::read(path);
+
+        // A truncated or foreign file is refused
+        let bytes = fs::read(path).unwrap();
+        fs::write(path, &bytes[..bytes.len() - 1]).unwrap();
+        let truncated = Tablebase::read(path);
+        fs::write(path, b"NOTATBL!").unwrap();
+        let foreign = Tablebase::read(path);
+        let mut huge = bytes[..17].to_vec();
+        huge[9..17].copy_from_slice(&u64::MAX.to_le_bytes());
+        fs::write(path, huge).unwrap();
+        let overflowing = Tablebase::read(path);
+        fs::remove_file(path).unwrap();
+
+        let read = read.unwrap();
+        assert_eq!(read.max_pieces, 2);
+        assert_eq!(read.entries, tablebase.entries);
+        assert!(!read.entries.is_empty());
+        for (value, outcome) in [(0, Outcome::Draw), (3, Outcome::Win(3)), (-1, Outcome::Loss(0)), (-8, Outcome::Loss(7))] {
+            assert_eq!(Outcome::decode(value), outcome);
+            assert_eq!(outcome.encode(), value);
+        }
+        assert!(truncated.is_err());
+        assert!(foreign.is_err());
+        assert!(overflowing.is_err());
+    }
+}
@@ -0,0 +1,240 @@
+// Engine-versus-engine matches. Each random opening is played twice, once
+// with either engine attacking, so that neither side of an unbalanced
+// opening favours one engine.
+
+use rand::seq::SliceRandom;
+
+use crate::archive::GameRecord;
+use crate::bot::{create_bot, BotConfig, EngineKind, MAX_LEVEL, MIN_LEVEL};
+use crate::engine::{Game, Move, Rules, Side, Variant};
+
+/// File the tournament games are saved to by default
+pub const TOURNAMENT_FILE: &str = "tournament.jsonl";
+
+/// Random moves played before the engines take over
+const OPENING_RANDOM_PLIES: usize = 4;
+
+/// Games reaching this length are scored as draws
+const MAX_GAME_PLIES: usize = 300;
+
+/// One of the two engines of a match
+#[derive(Debug, Clone)]
+pub struct Entrant {
+    pub engine: EngineKind,
+    pub level: u8,
+}
+
+impl Entrant {
+    /// Parses an engine with an optional level, e.g. "alphabeta", "mcts/5" or
+    /// "mcts:playouts=5000,c=0.8/5".
+    pub fn parse(spec: &str) -> Result<Entrant, String> {
+        let (name, level) = match spec.rsplit_once('/') {
+            Some((name, level)) => {
+                let level: u8 = level.parse().map_err(|_| format!("Invalid level: {}", level))?;
+                if !(MIN_LEVEL..=MAX_LEVEL).contains(&level) {
+                    return Err(format!("Levels go from {} to {}", MIN_LEVEL, MAX_LEVEL));
+                }
+                (name, level)
+            }
+            None => (spec, MAX_LEVEL),
+        };
+        let engine = EngineKind::parse(name)?;
+        Ok(Entrant { engine, level })
+    }
+
+    pub fn config(&self, side: Side, think_time_ms: u64) -> BotConfig {
+        let mut config = BotConfig::new(side, self.engine.clone(), self.level);
+        config.think_time_ms = think_time_ms;
+        config
+    }
+}
+
+/// Results of a match from the first engine's point of view
+#[derive(Debug, Clone, Default)]
+pub struct MatchResult {
+    pub wins: u32,
+    pub draws: u32,
+    pub losses: u32,
+    pub total_plies: usize,
+}
+
+impl MatchResult {
+    pub fn games(&self) -> u32 {
+        self.wins + self.draws + self.losses
+    }
+
+    /// Points per game, a draw counting half
+    pub fn score(&self) -> f64 {
+        (self.wins as f64 + self.draws as f64 / 2.0) / self.games().max(1) as f64
+    }
+
+    pub fn average_length(&self) -> f64 {
+        self.total_plies as f64 / self.games().max(1) as f64
+    }
+
+    /// Elo difference of the first engine over the second and the margin of
+    /// its 95% confidence interval. None while one engine has every point.
+    pub fn elo_difference(&self) -> Option<(f64, f64)> {
+        let games = self.games() as f64;
+        let score = self.score();
+        if games == 0.0 || score <= 0.0 || score >= 1.0 {
+            return None;
+        }
+        let elo = |score: f64| -400.0 * (1.0 / score - 1.0).log10();
+
+        // Standard error of the mean score over the games played
+        let variance = (self.wins as f64 * (1.0 - score).powi(2)
+            + self.draws as f64 * (0.5 - score).powi(2)
+            + self.losses as f64 * score.powi(2))
+            / games;
+        let error = (variance / games).sqrt() * 1.96;
+        let low = (score - error).max(1e-6);
+        let high = (score + error).min(1.0 - 1e-6);
+        Some((elo(score), (elo(high) - elo(low)) / 2.0))
+    }
+}
+
+/// Random legal moves that do not end the game
+fn random_opening(variant: Variant) -> Vec<Move> {
+    let mut rng = rand::thread_rng();
+    loop {
+        let mut game = Game::new(variant);
+        let mut moves = Vec::new();
+        while moves.len() < OPENING_RANDOM_PLIES {
+            match game.legal_moves().choose(&mut rng) {
+                Some(&mv) if game.play(mv).is_ok() && game.winner().is_none() => moves.push(mv),
+                _ => break,
+            }
+        }
+        if moves.len() == OPENING_RANDOM_PLIES {
+            return moves;
+        }
+    }
+}
+
+/// Plays one game between two bot configurations after the opening moves.
+fn play_game(variant: Variant, opening: &[Move], attacker: &BotConfig, defender: &BotConfig) -> Game {
+    let mut game = Game::new(variant);
+    for &mv in opening {
+        let _ = game.play(mv);
+    }
+    let mut bots = [create_bot(attacker, variant), create_bot(defender, variant)];
+    while game.winner().is_none() && game.history().len() < MAX_GAME_PLIES {
+        let [attacker, defender] = &mut bots;
+        let bot = if game.side_to_move() == Side::Attacker { attacker } else { defender };
+        match bot.choose_move(&game) {
+            Some(mv) if game.play(mv).is_ok() => {}
+            _ => break,
+        }
+    }
+    game
+}
+
+/// Plays `games` games between two engines, with the first engine attacking
+/// in the even-numbered ones. `on_game` receives each finished game and the
+/// standings so far.
+pub fn play_match(
+    variant: Variant,
+    entrants: [&Entrant; 2],
+    games: usize,
+    think_time_ms: u64,
+    mut on_game: impl FnMut(usize, &GameRecord, &MatchResult),
+) -> MatchResult {
+    let mut result = MatchResult::default();
+    let mut opening = Vec::new();
+
+    for index in 0..games {
+        // A new opening for every pair of games
+        if index % 2 == 0 {
+            opening = random_opening(variant);
+        }
+        let first_side = if index % 2 == 0 { Side::Attacker } else { Side::Defender };
+        let first = entrants[0].config(first_side, think_time_ms);
+        let second = entrants[1].config(first_side.opponent(), think_time_ms);
+        let (attacker, defender) = if first_side == Side::Attacker { (first, second) } else { (second, first) };
+
+        let game = play_game(variant, &opening, &attacker, &defender);
+        match game.winner() {
+            Some(side) if side == first_side => result.wins += 1,
+            Some(_) => result.losses += 1,
+            None => result.draws += 1,
+        }
+        result.total_plies += game.history().len();
+
+        let record = GameRecord { engines: Some([attacker, defender]), ..GameRecord::new(&game, None) };
+        on_game(index + 1, &record, &result);
+    }
+
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn result(wins: u32, draws: u32, losses: u32) -> MatchResult {
+        MatchResult { wins, draws, losses, total_plies: 0 }
+    }
+
+    #[test]
+    fn elo_follows_the_score() {
+        let (even, margin) = result(5, 10, 5).elo_difference().unwrap();
+        assert!(even.abs() < 1e-9);
+        assert!(margin > 0.0);
+
+        // Three points out of four is about 191 Elo, either way round
+        let (ahead, _) = result(3, 0, 1).elo_difference().unwrap();
+        let (behind, _) = result(1, 0, 3).elo_difference().unwrap();
+        assert!((ahead - 190.85).abs() < 0.01, "{}", ahead);
+        assert!((ahead + behind).abs() < 1e-9);
+        assert_eq!(result(2, 1, 0).score(), 5.0 / 6.0);
+
+        // More games narrow the interval
+        let (_, few) = result(6, 0, 4).elo_difference().unwrap();
+        let (_, many) = result(60, 0, 40).elo_difference().unwrap();
+        assert!(many < few);
+
+        assert_eq!(result(4, 0, 0).elo_difference(), None);
+        assert_eq!(result(0, 0, 4).elo_difference(), None);
+        assert_eq!(result(0, 0, 0).elo_difference(), None);
+    }
+
+    #[test]
+    fn entrants_parse_with_an_optional_level() {
+        let entrant = Entrant::parse("mcts:playouts=500/3").unwrap();
+        assert_eq!(entrant.engine, EngineKind::Mcts { playouts: 500, exploration: crate::bot::DEFAULT_EXPLORATION });
+        assert_eq!(entrant.level, 3);
+        assert_eq!(Entrant::parse("alphabeta").unwrap().level, MAX_LEVEL);
+        for spec in ["alphabeta/0", "alphabeta/9", "alphabeta/strong", "minimax/3"] {
+            assert!(Entrant::parse(spec).is_err(), "{}", spec);
+        }
+    }
+
+    #[test]
+    #[cfg(feature = "brandubh")]
+    fn matches_alternate_sides_over_each_opening() {
+        let first = Entrant::parse("alphabeta/1").unwrap();
+        let second = Entrant::parse("mcts:playouts=50/1").unwrap();
+        let mut records = Vec::new();
+        let result = play_match(Variant::Brandubh, [&first, &second], 4, 10, |index, record, standings| {
+            assert_eq!(standings.games() as usize, index);
+            records.push(record.clone());
+        });
+
+        assert_eq!(result.games(), 4);
+        assert_eq!(records.len(), 4);
+        assert_eq!(result.total_plies, records.iter().map(|record| record.moves.len()).sum::<usize>());
+        for (index, record) in records.iter().enumerate() {
+            let [attacker, defender] = record.engines.as_ref().unwrap();
+            let (first_config, second_config) = if index % 2 == 0 { (attacker, defender) } else { (defender, attacker) };
+            assert_eq!(first_config.engine, first.engine);
+            assert_eq!(second_config.engine, second.engine);
+            assert_eq!((attacker.side, defender.side), (Side::Attacker, Side::Defender));
+        }
+
+        // Both games of a pair start from the same opening
+        for pair in records.chunks(2) {
+            assert_eq!(pair[0].moves[..OPENING_RANDOM_PLIES], pair[1].moves[..OPENING_RANDOM_PLIES]);
+        }
+    }
+}
@@ -0,0 +1,225 @@
+// Texel-style tuning of the evaluation weights. Self-play games provide quiet
+// positions labelled with the final result, and the weights are adjusted so
+// that the evaluation, through a logistic curve, predicts those results.
+
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::thread;
+use std::time::Duration;
+
+use rand::seq::SliceRandom;
+
+use crate::engine::{Game, Rules, Side, Variant};
+use crate::eval::{features, Features, Weights, FEATURE_COUNT};
+use crate::search::{SearchLimits, Searcher};
+
+/// Random moves at the start of each self-play game, so the games differ
+const OPENING_RANDOM_PLIES: usize = 6;
+
+/// Self-play games reaching this length are scored as draws
+const MAX_GAME_PLIES: usize = 300;
+
+/// Search depth of the self-play bots
+const SELF_PLAY_DEPTH: u32 = 2;
+
+/// Scale of the evaluation when turning it into a win probability
+const EVAL_SCALE: f64 = 400.0;
+
+/// Largest weight change tried by the tuner, halved until it reaches 1
+const INITIAL_STEP: i32 = 16;
+
+/// A quiet position and the result of its game for the attackers
+/// (1 for a win, 0 for a loss, 0.5 for a draw)
+#[derive(Debug, Clone, Copy)]
+pub struct Sample {
+    pub features: Features,
+    pub result: f64,
+}
+
+/// A position is quiet when the side to move can neither capture nor win
+/// in one move, so that the static evaluation is meaningful.
+fn is_quiet<G: Rules>(game: &mut G) -> bool {
+    if game.winner().is_some() {
+        return false;
+    }
+    for mv in game.legal_moves() {
+        let quiet = match game.play(mv) {
+            Ok(captured) => {
+                let quiet = captured.is_empty() && game.winner().is_none();
+                let _ = game.undo();
+                quiet
+            }
+            Err(_) => true,
+        };
+        if !quiet {
+            return false;
+        }
+    }
+    true
+}
+
+/// Plays one self-play game and returns its quiet positions with the result.
+fn self_play_game(variant: Variant, searcher: &mut Searcher) -> Vec<Sample> {
+    let mut game = Game::new(variant);
+    let mut rng = rand::thread_rng();
+    let limits = SearchLimits { max_depth: SELF_PLAY_DEPTH, time_limit: Duration::from_secs(1), eval_noise: 0 };
+    let mut positions = Vec::new();
+
+    for ply in 0..MAX_GAME_PLIES {
+        if game.winner().is_some() {
+            break;
+        }
+        let mv = if ply < OPENING_RANDOM_PLIES {
+            game.legal_moves().choose(&mut rng).copied()
+        } else {
+            if is_quiet(&mut game) {
+                positions.push(features(&game));
+            }
+            searcher.search(&game, &limits).best_move
+        };
+        match mv {
+            Some(mv) if game.play(mv).is_ok() => {}
+            _ => break,
+        }
+    }
+
+    let result = match game.winner() {
+        Some(Side::Attacker) => 1.0,
+        Some(Side::Defender) => 0.0,
+        None => 0.5,
+    };
+    positions.into_iter().map(|features| Sample { features, result }).collect()
+}
+
+/// Plays self-play games with the given weights on all CPU cores and
+/// collects their quiet positions.
+pub fn self_play(variant: Variant, weights: &Weights, games: usize) -> Vec<Sample> {
+    let next_game = AtomicUsize::new(0);
+    let samples = Mutex::new(Vec::new());
+    let threads = thread::available_parallelism().map_or(1, |count| count.get()).min(games.max(1));
+
+    thread::scope(|scope| {
+        for _ in 0..threads {
+            scope.spawn(|| {
+                let mut searcher = Searcher::new(weights.clone());
+                while next_game.fetch_add(1, Ordering::Relaxed) < games {
+                    let game_samples = self_play_game(variant, &mut searcher);
+                    samples.lock().unwrap().extend(game_samples);
+                }
+            });
+        }
+    });
+
+    samples.into_inner().unwrap()
+}
+
+/// Mean squared difference between the results and the winning chances the
+/// evaluation predicts.
+pub fn error(samples: &[Sample], values: &[i32; FEATURE_COUNT]) -> f64 {
+    if samples.is_empty() {
+        return 0.0;
+    }
+    let total: f64 = samples
+        .iter()
+        .map(|sample| {
+            let score: i32 = values.iter().zip(sample.features.0).map(|(weight, count)| weight * count).sum();
+            let predicted = 1.0 / (1.0 + (-(score as f64) / EVAL_SCALE).exp());
+            (sample.result - predicted).powi(2)
+        })
+        .sum();
+    total / samples.len() as f64
+}
+
+/// Adjusts one weight at a time while that lowers the error, with steps
+/// halving from `INITIAL_STEP` down to 1. `report` is called once each
+/// step size is done, with the step and the current error.
+pub fn tune(samples: &[Sample], initial: &Weights, mut report: impl FnMut(i32, f64)) -> Weights {
+    let mut values = initial.values();
+    let mut best = error(samples, &values);
+    let mut step = INITIAL_STEP;
+
+    while step >= 1 {
+        let mut improved = true;
+        while improved {
+            improved = false;
+            for index in 0..FEATURE_COUNT {
+                for delta in [step, -step] {
+                    let mut candidate = values;
+                    candidate[index] += delta;
+                    let candidate_error = error(samples, &candidate);
+                    if candidate_error < best {
+                        best = candidate_error;
+                        values = candidate;
+                        improved = true;
+                        break;
+                    }
+                }
+            }
+        }
+        report(step, best);
+        step /= 2;
+    }
+
+    Weights::from_values(values)
+}
+
+#[cfg(test)]
+#[cfg(feature = "brandubh")]
+mod tests {
+    use super::*;
+
+    fn position(text: &str) -> Game {
+        Game::from_position(Variant::Brandubh, text).unwrap()
+    }
+
+    fn sample(counts: [i32; FEATURE_COUNT], result: f64) -> Sample {
+        Sample { features: Features(counts), result }
+    }
+
+    #[test]
+    fn only_quiet_positions_are_sampled() {
+        assert!(is_quiet(&mut position("7/7/7/3k3/7/7/1a5 a")));
+        // The attackers can capture on c3
+        assert!(!is_quiet(&mut position("7/7/7/3k3/1ad4/7/3a3 a")));
+        // The king can reach a1
+        assert!(!is_quiet(&mut position("7/7/7/7/7/k6/5a1 d")));
+    }
+
+    #[test]
+    fn error_compares_results_with_the_logistic_prediction() {
+        let values = Weights::default().values();
+        assert_eq!(error(&[], &values), 0.0);
+        assert_eq!(error(&[sample([0; FEATURE_COUNT], 1.0)], &values), 0.25);
+
+        // An advantage of 400 predicts a win 73% of the time
+        let mut values = [0; FEATURE_COUNT];
+        values[0] = 400;
+        let predicted = 1.0 / (1.0 + (-1.0f64).exp());
+        let samples = [sample([1, 0, 0, 0, 0, 0], 1.0), sample([1, 0, 0, 0, 0, 0], 0.0)];
+        let expected = ((1.0 - predicted).powi(2) + predicted.powi(2)) / 2.0;
+        assert!((error(&samples, &values) - expected).abs() < 1e-12);
+    }
+
+    #[test]
+    fn tuning_never_increases_the_error() {
+        // Positions where extra defenders win more often than the defaults predict
+        let samples = [
+            sample([8, -4, 3, 0, 2, 1], 0.0),
+            sample([8, -2, 4, 0, 1, 2], 0.5),
+            sample([6, -4, 2, -1, 3, 0], 0.0),
+            sample([8, -1, 5, 0, 0, 3], 1.0),
+            sample([4, -3, 1, -1, 4, 0], 0.0),
+        ];
+        let initial = Weights::default();
+        let initial_error = error(&samples, &initial.values());
+
+        let mut reports = Vec::new();
+        let tuned = tune(&samples, &initial, |step, error| reports.push((step, error)));
+        let tuned_error = error(&samples, &tuned.values());
+
+        assert!(tuned_error < initial_error, "{} >= {}", tuned_error, initial_error);
+        assert_eq!(reports.iter().map(|&(step, _)| step).collect::<Vec<_>>(), [16, 8, 4, 2, 1]);
+        assert!(reports.windows(2).all(|pair| pair[1].1 <= pair[0].1));
+        assert_eq!(reports.last().unwrap().1, tuned_error);
+    }
+}
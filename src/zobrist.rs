@@ -0,0 +1,52 @@
+// Zobrist keys shared by every variant for 64-bit position hashing.
+//
+// The keys are generated at compile time from a fixed seed, so a given
+// position hashes to the same value on every build and every run. This
+// keeps hashes stored on disk (opening books, game records) valid.
+
+/// Largest board size supported by the key table (Hnefatafl and Koch are 11x11)
+pub const MAX_BOARD_SIZE: usize = 11;
+
+/// Piece indices into the key table
+pub const ATTACKER: usize = 0;
+pub const DEFENDER: usize = 1;
+pub const KING: usize = 2;
+
+const PIECE_KINDS: usize = 3;
+const SEED: u64 = 0x7461_666c_6861_7368; // "taflhash"
+
+// One key per (square, piece kind), followed by the side-to-move key
+const KEY_COUNT: usize = MAX_BOARD_SIZE * MAX_BOARD_SIZE * PIECE_KINDS + 1;
+const KEYS: [u64; KEY_COUNT] = generate_keys();
+
+/// SplitMix64 step, used to fill the key table deterministically
+const fn splitmix64(state: u64) -> (u64, u64) {
+    let state = state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+    let mut z = state;
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    (state, z ^ (z >> 31))
+}
+
+const fn generate_keys() -> [u64; KEY_COUNT] {
+    let mut keys = [0; KEY_COUNT];
+    let mut state = SEED;
+    let mut i = 0;
+    while i < KEY_COUNT {
+        let (next_state, key) = splitmix64(state);
+        state = next_state;
+        keys[i] = key;
+        i += 1;
+    }
+    keys
+}
+
+/// Key for a piece kind (`ATTACKER`, `DEFENDER` or `KING`) standing on a square
+pub fn piece_key(piece: usize, pos: (usize, usize)) -> u64 {
+    KEYS[(pos.0 * MAX_BOARD_SIZE + pos.1) * PIECE_KINDS + piece]
+}
+
+/// Key toggled in when the defender is the side to move
+pub fn side_key() -> u64 {
+    KEYS[KEY_COUNT - 1]
+}